@@ -0,0 +1,13 @@
+	DO ,1 <- #2 BY #3
+	DO ,1 SUB #1 #1 <- #11
+	PLEASE DO ,1 SUB #1 #2 <- #12
+	DO ,1 SUB #1 #3 <- #13
+	DO ,1 SUB #2 #1 <- #21
+	DO ,1 SUB #2 #2 <- #22
+	DO ,1 SUB #2 #3 <- #23
+	PLEASE DO ;1 <- #3
+	DO ;1 SUB #1 <- #65535$#1
+	DO ;1 SUB #3 <- #3
+	DO READ OUT ,1 SUB #1 #1 + ,1 SUB #1 #3 + ,1 SUB #2 #2
+	PLEASE READ OUT ;1 SUB #1 + ;1 SUB #2 + ;1 SUB #3
+	PLEASE GIVE UP
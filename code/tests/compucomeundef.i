@@ -0,0 +1,5 @@
+	DO .1 <- #7
+(1)	PLEASE READ OUT #1
+(2)	DO READ OUT #2
+	DO COME FROM .1
+	DO GIVE UP
@@ -0,0 +1,6 @@
+	DO ,1 <- #3
+	DO ,1 SUB #2 <- #7
+	PLEASE READ OUT ,1 SUB #2
+	DO ,2 <- #65535 BY #65535 BY #65535 BY #65535 BY #65535
+	DO READ OUT ,1 SUB #1
+	PLEASE GIVE UP
@@ -0,0 +1,5 @@
+	DO .1 <- #3
+	PLEASE READ OUT .1
+	DO .1 <- #2 BY #2
+	DO READ OUT .1
+	PLEASE GIVE UP
@@ -0,0 +1,6 @@
+	DO :1 <- #1234$#5678
+	DO :2 <- '"'?":1~"#65535$#0""$#65535'~"#0$#65535""$"'?":1~"#0$#65535""$#65535'~"#0$#65535""'
+	PLEASE READ OUT :2
+	DO :3 <- '"'?"'"'?":1~"#65535$#0""$#65535'~"#0$#65535""$"'?":1~"#0$#65535""$#65535'~"#0$#65535""'~"#65535$#0""$#65535'~"#0$#65535""$"'?"'"'?":1~"#65535$#0""$#65535'~"#0$#65535""$"'?":1~"#0$#65535""$#65535'~"#0$#65535""'~"#0$#65535""$#65535'~"#0$#65535""'
+	DO READ OUT :3
+	PLEASE GIVE UP
@@ -0,0 +1,3 @@
+	(1) DO .1 <- #1
+	(1) PLEASE READ OUT .1
+	DO GIVE UP
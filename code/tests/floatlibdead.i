@@ -0,0 +1,5 @@
+	DO .1 <- #3
+	PLEASE READ OUT .1
+	DO GIVE UP
+	DO (5000) NEXT
+	PLEASE (5030) NEXT
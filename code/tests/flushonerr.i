@@ -0,0 +1,9 @@
+	DO ,1 <- #5
+	PLEASE DO ,1SUB#1 <- #234
+	DO ,1SUB#2 <- #112
+	DO ,1SUB#3 <- #112
+	DO ,1SUB#4 <- #0
+	DO ,1SUB#5 <- #64
+	PLEASE READ OUT ,1
+	DO RESUME #0
+	PLEASE GIVE UP
@@ -0,0 +1,9 @@
+	DO :1 <- #65535$#0
+	DO READ OUT :1
+	DO :2 <- "#65535$#0"~"#65535$#65535"
+	PLEASE READ OUT :2
+	DO :3 <- "#21845$#65535"~"#0$#65535"
+	DO READ OUT :3
+	DO :4 <- "#1$#3"~"#1$#3"
+	DO READ OUT :4
+	PLEASE GIVE UP
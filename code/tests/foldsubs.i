@@ -0,0 +1,7 @@
+	DO ,1 <- #0$#2
+	DO ,1 SUB #0$#2 <- #7
+	PLEASE WRITE IN ,1 SUB #0$#1
+	DO .1 <- ,1 SUB "#0$#2"~"#0$#2"
+	DO READ OUT ,1 SUB #0$#2
+	PLEASE READ OUT .1
+	DO GIVE UP
@@ -0,0 +1 @@
+THREE
@@ -0,0 +1,14 @@
+	DO .1 <- &'#128$#1'
+	DO READ OUT .1
+	DO .2 <- #128
+	DO .1 <- &'.2$#1'
+	DO READ OUT .1
+	PLEASE :1 <- V'#128$#1'
+	DO READ OUT :1
+	DO :1 <- V'.2$#1'
+	DO READ OUT :1
+	DO :1 <- ?'#128$#1'
+	PLEASE READ OUT :1
+	DO :1 <- ?'.2$#1'
+	DO READ OUT :1
+	PLEASE GIVE UP
@@ -0,0 +1,7 @@
+	DO (1) NEXT
+	DO GIVE UP
+(1)	DO FORGET #5
+	PLEASE READ OUT #1
+	DO (2) NEXT
+(2)	DO RESUME #3
+	PLEASE GIVE UP
@@ -0,0 +1,7 @@
+	DO .1 <- #1
+	PLEASE READ OUT .1
+	DO READ OUT #2
+	DO COME FROM READING OUT
+	DO ABSTAIN FROM COMING FROM
+	PLEASE READ OUT #3
+	DO GIVE UP
@@ -0,0 +1,16 @@
+	DO .1 <- #1
+	DO STASH .1
+	DO .1 <- #2
+	DO IGNORE .1
+	DO .1 <- #3
+	PLEASE RETRIEVE .1
+	DO READ OUT .1
+	DO .1 <- #3
+	DO READ OUT .1
+	DO STASH .1
+	PLEASE REMEMBER .1
+	DO .1 <- #4
+	DO READ OUT .1
+	PLEASE RETRIEVE .1
+	DO READ OUT .1
+	PLEASE GIVE UP
@@ -0,0 +1,10 @@
+        DO ,1 <- #2
+        DO ,1 SUB #1 <- #1
+        PLEASE DO ,1 SUB #2 <- #2
+        DO IGNORE ,1
+        DO ,1 SUB #1 <- #3
+        PLEASE DO ,1 SUB #2 <- #4
+        DO WRITE IN ,1
+        DO REMEMBER ,1
+        DO READ OUT ,1 SUB #1 + ,1 SUB #2
+        PLEASE GIVE UP
@@ -0,0 +1 @@
+ab
\ No newline at end of file
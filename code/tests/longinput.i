@@ -0,0 +1,7 @@
+	DO WRITE IN .1
+	DO READ OUT .1
+	DO WRITE IN :1
+	PLEASE READ OUT :1
+	DO WRITE IN :1
+	DO READ OUT :1
+	PLEASE GIVE UP
@@ -0,0 +1,3 @@
+ZERO ZERO ONE
+ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO FOUR TWO
+ONE TWO THREE FOUR FIVE SIX SEVEN EIGHT NINE ZERO ONE
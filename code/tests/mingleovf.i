@@ -0,0 +1,10 @@
+	DO :1 <- #65535$#65535
+	DO READ OUT :1
+	DO :1 <- #65535$#0
+	PLEASE READ OUT :1
+	DO .1 <- #65535
+	DO :2 <- .1$.1
+	DO READ OUT :2
+	DO :3 <- :1$#0
+	PLEASE READ OUT :3
+	DO GIVE UP
@@ -0,0 +1,17 @@
+	DO .1 <- #1
+	(10) DO READ OUT .1
+	DO .1 <- '.1$#1'~#7
+	PLEASE READ OUT .1
+	DO .2 <- #3
+	(20) DO .2 <- #4
+	DO READ OUT .2
+	PLEASE GIVE UP
+	DO NEXT FROM (10)
+	DO .1 <- '.1$#1'~#3
+	DO READ OUT .1
+	PLEASE RESUME #1
+	DO NEXT FROM (20)
+	DO READ OUT .2
+	PLEASE FORGET #1
+	DO READ OUT #5
+	PLEASE GIVE UP
@@ -0,0 +1,13 @@
+	DO .2 <- #5
+	DO .1 <- 'V"#0$.2"'~"#0$#65535"
+	PLEASE READ OUT .1
+	DO .1 <- 'V".2$#0"'~"#0$#65535"
+	DO READ OUT .1
+	DO :1 <- #65535$#1
+	PLEASE :2 <- :1~"#65535$#65535"
+	DO READ OUT :2
+	DO .1 <- .2~"#65535$#65535"
+	DO READ OUT .1
+	DO :2 <- '&"#65535$.2"'~"#0$#65535"
+	DO READ OUT :2
+	PLEASE GIVE UP
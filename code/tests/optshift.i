@@ -0,0 +1,6 @@
+	DO :1 <- #48879$#57005
+	DO :2 <- ":1~'#65280$#65280'"~'#65520$#65520'
+	DO READ OUT :2
+	PLEASE DO :2 <- ":1~'#65280$#65280'"~"#32768$#0"
+	DO READ OUT :2
+	PLEASE GIVE UP
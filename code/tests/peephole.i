@@ -0,0 +1,19 @@
+	DO .1 <- #12
+	DO .2 <- #10
+	DO .3 <- '&".1$.2"'~"#0$#65535"
+	PLEASE READ OUT .3
+	DO .3 <- 'V".1$.2"'~"#0$#65535"
+	DO READ OUT .3
+	DO .3 <- '?".1$.2"'~"#0$#65535"
+	DO READ OUT .3
+	PLEASE :1 <- #65535$#0
+	DO :2 <- .1$.2
+	DO :3 <- "'&"':1~"#65535$#0"'$':2~"#65535$#0"'"'~"#0$#65535" "$
+	          "'&"':1~"#0$#65535"'$':2~"#0$#65535"'"'~"#0$#65535" "
+	DO READ OUT :3
+	DO .4 <- '.1~.1'~#1
+	PLEASE READ OUT .4
+	DO .5 <- #0
+	DO .4 <- '.5~.5'~#1
+	DO READ OUT .4
+	PLEASE GIVE UP
@@ -0,0 +1,6 @@
+	DO .1 <- #12
+	DO :1 <- #1$#2
+	PLEASE READ OUT .1~#12
+	DO READ OUT #3$#5 + .1 + "V.1"~#15
+	DO READ OUT &:1 + #7
+	PLEASE GIVE UP
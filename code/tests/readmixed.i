@@ -0,0 +1,11 @@
+	DO ,1 <- #3
+	PLEASE DO ,1SUB#1 <- #234
+	DO ,1SUB#2 <- #112
+	DO ,1SUB#3 <- #112
+	DO ,2 <- #2
+	DO ,2SUB#1 <- #0
+	DO ,2SUB#2 <- #64
+	DO .1 <- #4
+	DO :1 <- #1$#1
+	PLEASE READ OUT .1 + ,1 + ,1 SUB #2 + ,2 + :1
+	PLEASE GIVE UP
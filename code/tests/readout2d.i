@@ -0,0 +1,4 @@
+	DO ,1 <- #2 BY #2
+	DO ,1 SUB #1 #2 <- #65
+	PLEASE READ OUT ,1
+	DO GIVE UP
@@ -0,0 +1,6 @@
+	DO REINSTATE (99)
+	DO ABSTAIN FROM (1)
+	PLEASE REINSTATE (98)
+(1)	DO READ OUT #1
+	DO READ OUT #2
+	PLEASE GIVE UP
@@ -0,0 +1,18 @@
+	DO WRITE IN .1
+	DO .2 <- '&".1$.1"'~"#0$#65535"
+	PLEASE READ OUT .2
+	DO .2 <- 'V".1$.1"'~"#0$#65535"
+	DO READ OUT .2
+	DO .2 <- '?".1$.1"'~"#0$#65535"
+	DO READ OUT .2
+	PLEASE WRITE IN :1
+	DO :2 <- "'&"':1~"#65535$#0"'$':1~"#65535$#0"'"'~"#0$#65535" "$
+	          "'&"':1~"#0$#65535"'$':1~"#0$#65535"'"'~"#0$#65535" "
+	DO READ OUT :2
+	DO :2 <- "'V"':1~"#65535$#0"'$':1~"#65535$#0"'"'~"#0$#65535" "$
+	          "'V"':1~"#0$#65535"'$':1~"#0$#65535"'"'~"#0$#65535" "
+	PLEASE READ OUT :2
+	DO :2 <- "'?"':1~"#65535$#0"'$':1~"#65535$#0"'"'~"#0$#65535" "$
+	          "'?"':1~"#0$#65535"'$':1~"#0$#65535"'"'~"#0$#65535" "
+	DO READ OUT :2
+	PLEASE GIVE UP
@@ -0,0 +1,2 @@
+ONE TWO
+ONE TWO THREE FOUR FIVE SIX SEVEN EIGHT NINE
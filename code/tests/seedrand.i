@@ -0,0 +1,12 @@
+	PLEASE NOTE THAT WITH A FIXED SEED, THE STOCHASTIC ROUTINES
+	PLEASE NOTE THAT GIVE THE SAME OUTPUT ON EVERY RUN
+	DO (1900) NEXT
+	DO READ OUT .1
+	DO .1 <- #100
+	DO (1910) NEXT
+	DO READ OUT .2
+	DO (1910) NEXT
+	DO READ OUT .2
+	DO (1900) NEXT
+	DO READ OUT .1
+	PLEASE GIVE UP
@@ -0,0 +1,4 @@
+	DO .1 <- #1 PLEASE READ OUT .1
+	DO .2 <- #2 DO .3 <- .2~#0 PLEASE DO .1 <- #0 DO READ OUT .2
+	DO RESUME .1 DO READ OUT .3
+	PLEASE GIVE UP
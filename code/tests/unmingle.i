@@ -0,0 +1,12 @@
+	DO .1 <- #1234
+	DO .2 <- #65535
+	DO .3 <- '.1$.2'~"#65535$#0"
+	PLEASE READ OUT .3
+	DO .3 <- '.1$.2'~"#0$#65535"
+	DO READ OUT .3
+	DO .3 <- '#7$.1'~"#65535$#0"
+	DO READ OUT .3
+	PLEASE :1 <- #65535$#65535
+	DO .3 <- '.1$:1'~"#65535$#0"
+	DO READ OUT .3
+	PLEASE GIVE UP
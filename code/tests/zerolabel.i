@@ -0,0 +1,3 @@
+	(0) DO .1 <- #1
+	PLEASE READ OUT .1
+	DO GIVE UP
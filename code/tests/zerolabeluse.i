@@ -0,0 +1,3 @@
+	DO .1 <- #1
+	PLEASE ABSTAIN FROM (0)
+	DO GIVE UP
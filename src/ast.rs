@@ -33,7 +33,7 @@ pub type Label = u16;
 pub type LogLine = u16;
 
 /// A whole program, with meta-information used at eval-time.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Program {
     /// Statements in the program.
     pub stmts: Vec<Stmt>,
@@ -49,13 +49,20 @@ pub struct Program {
     /// True if we added the syslib or floatlib to the program.
     pub added_syslib: bool,
     pub added_floatlib: bool,
+    /// Index of the first statement of the floatlib, if the parser added it
+    /// and it is still the last part of the program.
+    pub floatlib_start: Option<usize>,
     /// The line on which the compiler bug E774 should be triggered.
     /// If this is set to a number >= stmts.len(), the bug is disabled.
+    ///
+    /// The parser picks the line (with a 1 in 10 chance of having a bug at
+    /// all), so the interpreter and compiled programs fail deterministically
+    /// when reaching it, like a real compiler bug would.
     pub bugline: LogLine,
 }
 
 /// A single statement.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Stmt {
     pub body: StmtBody,
     pub props: StmtProps,
@@ -65,13 +72,20 @@ pub struct Stmt {
 }
 
 /// Common properties for all statements.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct StmtProps {
     /// Source line of the statement.
     pub srcline: SrcLine,
+    /// Column of the statement's DO/PLEASE within its source line.
+    pub srccol: usize,
+    /// Position of the statement among those starting on its source line,
+    /// from 1 (0 if unknown).
+    pub stmt_on_line: usize,
     /// Source line of the next statement (provides "on the way to") in error
     /// messages.
     pub onthewayto: SrcLine,
+    /// Column of the next statement within its line.
+    pub onthewaycol: usize,
     /// Label of the line, or zero if no label.
     pub label: Label,
     /// Execution chance in %, usually 100.
@@ -83,7 +97,7 @@ pub struct StmtProps {
 }
 
 /// Type-of-statement dependent data.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum StmtBody {
     /// An undecodable statement ("splat"), resulting in a runtime error when
     /// executed (and not abstained).
@@ -94,6 +108,9 @@ pub enum StmtBody {
     Dim(Var, Vec<Expr>),
     DoNext(Label),
     ComeFrom(ComeFrom),
+    /// Like COME FROM, but also pushes the origin onto the NEXT stack.  Only
+    /// labels and gerunds are allowed as targets.
+    NextFrom(ComeFrom),
     Resume(Expr),
     Forget(Expr),
     Ignore(Vec<Var>),
@@ -143,6 +160,8 @@ pub enum Expr {
     RsAnd(Box<Expr>, Box<Expr>),
     RsOr(Box<Expr>, Box<Expr>),
     RsXor(Box<Expr>, Box<Expr>),
+    /// Shifts work on 32 bits, whatever the type of the operand; shifting by
+    /// 32 or more gives zero.
     RsRshift(Box<Expr>, Box<Expr>),
     RsLshift(Box<Expr>, Box<Expr>),
     // RsEqual(Box<Expr>, Box<Expr>),
@@ -162,7 +181,7 @@ pub enum VType {
 }
 
 /// Specification of targets for an ABSTAIN or REINSTATE.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Abstain {
     Label(Label),
     Calc,
@@ -176,22 +195,32 @@ pub enum Abstain {
     Abstain,
     Reinstate,
     ComeFrom,
+    NextFrom,
     ReadOut,
     WriteIn,
     TryAgain,
 }
 
 /// Specification of the target for a COME FROM.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum ComeFrom {
     Label(Label),
+    /// Computed COME FROM: the expression is evaluated whenever a labeled
+    /// statement is checked.  A value that is not a defined label is no error,
+    /// the COME FROM just does not trigger (programs rely on this to disable it);
+    /// but a value above 65535 can't be a label at all, and is an error (IE275).
     Expr(Expr),
+    /// Gerund COME FROM: comes from every statement of that type.  Like labels,
+    /// this is resolved by the parser; a statement that more than one COME FROM
+    /// would come from is an error (IE555).
     Gerund(Abstain),
 }
 
 /// Information about a variable.
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct VarInfo {
+    /// Number of the variable in the source (the parser renumbers them).
+    pub orig: usize,
     /// Variable is IGNOREd somewhere in the program.
     pub can_ignore: bool,
     /// Variable is STASHed somewhere in the program.
@@ -199,6 +228,51 @@ pub struct VarInfo {
 }
 
 
+impl Program {
+    /// Get a copy of the variable reference with its number from the source,
+    /// for use in messages to the user.
+    pub fn source_var(&self, var: &Var) -> Var {
+        let mut var = var.clone();
+        SourceNames(self).walk_var(&mut var);
+        var
+    }
+
+    /// Get the number from the source of a variable, ignoring subscripts.
+    fn source_number(&self, var: &Var) -> usize {
+        match *var {
+            Var::I16(n) => self.var_info.0[n].orig,
+            Var::I32(n) => self.var_info.1[n].orig,
+            Var::A16(n, _) => self.var_info.2[n].orig,
+            Var::A32(n, _) => self.var_info.3[n].orig,
+        }
+    }
+
+    /// Get a copy of the expression with all variables numbered as in the
+    /// source, for use in messages to the user.
+    pub fn source_expr(&self, expr: &Expr) -> Expr {
+        let mut expr = expr.clone();
+        SourceNames(self).walk_expr(&mut expr);
+        expr
+    }
+
+    /// Get the statement with the given label, if there is one.
+    pub fn stmt_by_label(&self, lbl: Label) -> Option<&Stmt> {
+        self.labels.get(&lbl).map(|&i| &self.stmts[i as usize])
+    }
+
+    /// List the distinct variables referenced anywhere in the program, in order
+    /// of first use.  Arrays are listed without subscripts.
+    #[allow(dead_code)]  // for tooling
+    pub fn used_vars(&self) -> Vec<Var> {
+        let mut used = UsedVars(Vec::new());
+        for stmt in &self.stmts {
+            // the visitors work on mutable trees
+            used.visit_stmt(&mut stmt.clone());
+        }
+        used.0
+    }
+}
+
 impl Stmt {
     /// Determine the abstain type for the statement. Label(0) is used as an
     /// escape value.
@@ -207,6 +281,7 @@ impl Stmt {
             StmtBody::Calc(..) | StmtBody::Dim(..) => Abstain::Calc,
             StmtBody::DoNext(_) => Abstain::Next,
             StmtBody::ComeFrom(_) => Abstain::ComeFrom,
+            StmtBody::NextFrom(_) => Abstain::NextFrom,
             StmtBody::Resume(_) => Abstain::Resume,
             StmtBody::Forget(_) => Abstain::Forget,
             StmtBody::Ignore(_) => Abstain::Ignore,
@@ -296,11 +371,154 @@ impl Var {
             Var::I32(..) | Var::A32(..) => VType::I32,
         }
     }
+
+    /// Format subscripts like in the source, i.e. `SUB x y`.
+    fn fmt_subs(fmt: &mut Formatter, subs: &[Expr]) -> Result<(), Error> {
+        if !subs.is_empty() {
+            write!(fmt, " SUB")?;
+        }
+        for sub in subs {
+            write!(fmt, " {}", sub)?;
+        }
+        Ok(())
+    }
+}
+
+/// Traversal of expression trees, for passes that transform expressions or
+/// look at the variables referenced.
+pub trait ExprVisitor {
+    /// Process a single expression; its operands have already been visited.
+    /// Does nothing by default.
+    fn visit_expr(&mut self, _expr: &mut Expr) { }
+
+    /// Process a single variable reference; the subscripts of an array element
+    /// are visited after it.  Does nothing by default.
+    fn visit_var(&mut self, _var: &mut Var) { }
+
+    /// Visit all operands of an expression, including the subscripts of array
+    /// elements, then the expression itself.
+    fn walk_expr(&mut self, expr: &mut Expr) {
+        match *expr {
+            Expr::Mingle(ref mut vx, ref mut wx) |
+            Expr::Select(_, ref mut vx, ref mut wx) |
+            Expr::RsAnd(ref mut vx, ref mut wx) |
+            Expr::RsOr(ref mut vx, ref mut wx) |
+            Expr::RsXor(ref mut vx, ref mut wx) |
+            Expr::RsRshift(ref mut vx, ref mut wx) |
+            Expr::RsLshift(ref mut vx, ref mut wx) |
+            Expr::RsNotEqual(ref mut vx, ref mut wx) |
+            Expr::RsMinus(ref mut vx, ref mut wx) |
+            Expr::RsPlus(ref mut vx, ref mut wx) => {
+                self.walk_expr(vx);
+                self.walk_expr(wx);
+            }
+            Expr::And(_, ref mut vx) |
+            Expr::Or(_, ref mut vx) |
+            Expr::Xor(_, ref mut vx) |
+            Expr::RsNot(ref mut vx) => self.walk_expr(vx),
+            Expr::Var(ref mut var) => self.walk_var(var),
+            Expr::Num(..) => { }
+        }
+        self.visit_expr(expr);
+    }
+
+    /// Visit a variable, then its subscripts if it is an array element.
+    fn walk_var(&mut self, var: &mut Var) {
+        self.visit_var(var);
+        if let Var::A16(_, ref mut subs) | Var::A32(_, ref mut subs) = *var {
+            for sub in subs {
+                self.walk_expr(sub);
+            }
+        }
+    }
+}
+
+/// Renames the variables in an expression to their source numbers.
+struct SourceNames<'a>(&'a Program);
+
+impl<'a> ExprVisitor for SourceNames<'a> {
+    fn visit_var(&mut self, var: &mut Var) {
+        let orig = self.0.source_number(var);
+        var.rename(orig);
+    }
+}
+
+/// Collects the distinct variables referenced, in order of first use and
+/// without subscripts.
+struct UsedVars(Vec<Var>);
+
+impl ExprVisitor for UsedVars {
+    fn visit_var(&mut self, var: &mut Var) {
+        let key = var.unique();
+        if !self.0.iter().any(|v| v.unique() == key) {
+            self.0.push(match *var {
+                Var::A16(n, _) => Var::A16(n, vec![]),
+                Var::A32(n, _) => Var::A32(n, vec![]),
+                ref v => v.clone(),
+            });
+        }
+    }
+}
+
+impl StmtVisitor for UsedVars { }
+
+/// Traversal of the statements of a program.  By default every expression and
+/// variable reference is walked: the expressions of calculations, DIMENSION,
+/// RESUME, FORGET, READ OUT, computed ABSTAIN and computed COME FROM, and the
+/// variables assigned to, written in, STASHed, RETRIEVEd, IGNOREd or
+/// REMEMBERed.
+pub trait StmtVisitor: ExprVisitor {
+    /// Process a single statement.  Walks all its parts by default.
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        self.walk_stmt(stmt);
+    }
+
+    /// Visit all expressions and variable references in a statement.
+    fn walk_stmt(&mut self, stmt: &mut Stmt) {
+        match stmt.body {
+            StmtBody::Calc(ref mut var, ref mut expr) => {
+                self.walk_var(var);
+                self.walk_expr(expr);
+            }
+            StmtBody::Dim(ref mut var, ref mut exprs) => {
+                self.walk_var(var);
+                for expr in exprs {
+                    self.walk_expr(expr);
+                }
+            }
+            StmtBody::Resume(ref mut expr) |
+            StmtBody::Forget(ref mut expr) |
+            StmtBody::Abstain(Some(ref mut expr), _) |
+            StmtBody::ComeFrom(ComeFrom::Expr(ref mut expr)) |
+            StmtBody::NextFrom(ComeFrom::Expr(ref mut expr)) => self.walk_expr(expr),
+            StmtBody::ReadOut(ref mut exprs) => {
+                for expr in exprs {
+                    self.walk_expr(expr);
+                }
+            }
+            StmtBody::Ignore(ref mut vars) |
+            StmtBody::Remember(ref mut vars) |
+            StmtBody::Stash(ref mut vars) |
+            StmtBody::Retrieve(ref mut vars) |
+            StmtBody::WriteIn(ref mut vars) => {
+                for var in vars {
+                    self.walk_var(var);
+                }
+            }
+            _ => { }
+        }
+    }
+
+    fn walk_program(&mut self, program: &mut Program) {
+        for stmt in &mut program.stmts {
+            self.visit_stmt(stmt);
+        }
+    }
 }
 
 impl VarInfo {
-    pub fn new() -> VarInfo {
-        VarInfo { can_ignore: true, can_stash: true }
+    pub fn new(orig: usize) -> VarInfo {
+        VarInfo { orig, can_ignore: true, can_stash: true }
     }
 }
 
@@ -309,7 +527,10 @@ impl Default for StmtProps {
     fn default() -> StmtProps {
         StmtProps { label: 0,
                     srcline: 0,
+                    srccol: 0,
+                    stmt_on_line: 0,
                     onthewayto: 0,
+                    onthewaycol: 0,
                     chance: 100,
                     polite: false,
                     disabled: false, }
@@ -362,6 +583,7 @@ impl Display for StmtBody {
                                                         self.fmt_bylist(exprs)),
             StmtBody::DoNext(ref line) => write!(fmt, "({}) NEXT", line),
             StmtBody::ComeFrom(ref spec) => write!(fmt, "COME FROM {}", spec),
+            StmtBody::NextFrom(ref spec) => write!(fmt, "NEXT FROM {}", spec),
             StmtBody::Resume(ref expr) => write!(fmt, "RESUME {}", expr),
             StmtBody::Forget(ref expr) => write!(fmt, "FORGET {}", expr),
             StmtBody::Ignore(ref vars) => write!(fmt, "IGNORE {}", self.fmt_pluslist(vars)),
@@ -377,7 +599,7 @@ impl Display for StmtBody {
             StmtBody::ReadOut(ref vars) => write!(fmt, "READ OUT {}", self.fmt_pluslist(vars)),
             StmtBody::TryAgain => write!(fmt, "TRY AGAIN"),
             StmtBody::GiveUp => write!(fmt, "GIVE UP"),
-            StmtBody::Print(_) => write!(fmt, "<PRINT>"),
+            StmtBody::Print(ref s) => write!(fmt, "<PRINT {} BYTES>", s.len()),
         }
     }
 }
@@ -389,17 +611,11 @@ impl Display for Var {
             Var::I32(n) => write!(fmt, ":{}", n),
             Var::A16(n, ref subs) => {
                 write!(fmt, ",{}", n)?;
-                for sub in subs {
-                    write!(fmt, " SUB {}", sub)?;
-                }
-                Ok(())
+                Var::fmt_subs(fmt, subs)
             }
             Var::A32(n, ref subs) => {
                 write!(fmt, ";{}", n)?;
-                for sub in subs {
-                    write!(fmt, " SUB {}", sub)?;
-                }
-                Ok(())
+                Var::fmt_subs(fmt, subs)
             }
         }
     }
@@ -448,6 +664,7 @@ impl Display for Abstain {
             Abstain::Abstain => write!(fmt, "ABSTAINING"),
             Abstain::Reinstate => write!(fmt, "REINSTATING"),
             Abstain::ComeFrom => write!(fmt, "COMING FROM"),
+            Abstain::NextFrom => write!(fmt, "NEXTING FROM"),
             Abstain::ReadOut => write!(fmt, "READING OUT"),
             Abstain::WriteIn => write!(fmt, "WRITING IN"),
             Abstain::TryAgain => write!(fmt, "TRYING AGAIN"),
@@ -0,0 +1,111 @@
+// -------------------------------------------------------------------------------------------------
+// Rick, a Rust intercal compiler.  Save your souls!
+//
+// Copyright (c) 2015-2017 Georg Brandl
+//
+// This program is free software; you can redistribute it and/or modify it under the terms of the
+// GNU General Public License as published by the Free Software Foundation; either version 2 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without
+// even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with this program;
+// if not, write to the Free Software Foundation, Inc., 675 Mass Ave, Cambridge, MA 02139, USA.
+// -------------------------------------------------------------------------------------------------
+
+//! Benchmarks for the optimizer and interpreter, using the `tpk.i` example
+//! program whose performance motivated most of the optimizer's patterns.
+//!
+//! Run with `cargo bench`.  All I/O goes through `Eval::run`, so the benchmarks
+//! do not touch the console.  Optimizer time alone is the difference between
+//! `tpk_parse_optimize` and `tpk_parse`.
+//!
+//! `sort.i` uses no arrays at all, for comparing the interpreter's speed on
+//! purely scalar code.
+//!
+//! `roman_output` prints the same few small numbers over and over, which the
+//! interpreter converts to Roman numerals only once; `roman_convert` does all
+//! the conversions for the same output, for comparison.
+
+extern crate test;
+
+use self::test::Bencher;
+
+use crate::ast::Program;
+use crate::eval::Eval;
+use crate::opt::Optimizer;
+use crate::parse::Parser;
+use crate::stdops::to_roman;
+
+const TPK_CODE: &str = include_str!("../code/tpk.i");
+const TPK_INPUT: &[u8] = include_bytes!("../code/tpk.tst");
+const TPK_OUTPUT: &[u8] = include_bytes!("../code/tpk.chk");
+const SORT_CODE: &str = include_str!("../code/sort.i");
+const SORT_INPUT: &[u8] = include_bytes!("../code/sort.tst");
+const SORT_OUTPUT: &[u8] = include_bytes!("../code/sort.chk");
+
+fn parse() -> Program {
+    Parser::new(TPK_CODE, 1, false).get_program().expect("tpk.i does not parse")
+}
+
+fn parse_optimize() -> Program {
+    // the constant output pass does not apply, since the program takes input
+    Optimizer::new(parse(), false).optimize().0
+}
+
+fn run(program: &Program) {
+    let (output, _) = Eval::run(program, TPK_INPUT, Some(0)).expect("tpk.i fails");
+    assert_eq!(output, TPK_OUTPUT);
+}
+
+#[bench]
+fn tpk_parse(b: &mut Bencher) {
+    b.iter(parse);
+}
+
+#[bench]
+fn tpk_parse_optimize(b: &mut Bencher) {
+    b.iter(parse_optimize);
+}
+
+#[bench]
+fn tpk_run(b: &mut Bencher) {
+    let program = parse_optimize();
+    b.iter(|| run(&program));
+}
+
+#[bench]
+fn tpk_end_to_end(b: &mut Bencher) {
+    b.iter(|| run(&parse_optimize()));
+}
+
+#[bench]
+fn sort_run(b: &mut Bencher) {
+    let program = Parser::new(SORT_CODE, 1, false).get_program().expect("sort.i does not parse");
+    let program = Optimizer::new(program, false).optimize().0;
+    b.iter(|| {
+        let (output, _) = Eval::run(&program, SORT_INPUT, Some(0)).expect("sort.i fails");
+        assert_eq!(output, SORT_OUTPUT);
+    });
+}
+
+/// The numbers printed by the `roman_output` benchmark program.
+fn roman_values() -> impl Iterator<Item = u32> {
+    (0..1000).map(|i| [4, 9, 42, 1999, 3888][i % 5])
+}
+
+#[bench]
+fn roman_output(b: &mut Bencher) {
+    let code: String = roman_values().enumerate().map(|(i, val)| {
+        format!("{} READ OUT #{}\n", if i % 4 == 0 { "PLEASE" } else { "DO" }, val)
+    }).collect::<String>() + "PLEASE GIVE UP\n";
+    let program = Parser::new(&code, 1, false).get_program().expect("program does not parse");
+    b.iter(|| Eval::run(&program, &[], Some(0)).expect("program fails"));
+}
+
+#[bench]
+fn roman_convert(b: &mut Bencher) {
+    b.iter(|| roman_values().map(to_roman).collect::<String>());
+}
@@ -159,7 +159,7 @@ impl Generator {
                 "None".into()
             };
             let label = format!("{}", stmt.props.label);
-            self.gen_comefrom_check(&cand1, &label)?;
+            self.gen_comefrom_check(&cand1, &label, "pctr")?;
         } else if let Some(next) = stmt.comefrom {
             let chance = self.program.stmts[next as usize].props.chance;
             w!(self.o, 16; "if abstain[{}] == 0 {{   // COME FROM", next);
@@ -167,6 +167,9 @@ impl Generator {
                 w!(self.o, 18; "let passed = check_chance({}, &mut rand_st);", chance);
                 w!(self.o, 18; "if passed {{");
             }
+            if let StmtBody::NextFrom(_) = self.program.stmts[next as usize].body {
+                self.gen_nextfrom_push("pctr")?;
+            }
             w!(self.o, 20; "pctr = {};", next);
             w!(self.o, 20; "continue;");
             if chance < 100 {
@@ -205,7 +208,7 @@ impl Generator {
             StmtBody::Error(ref e) => {
                 w!(self.o, 20; "return {};", e.to_code());
             }
-            StmtBody::ComeFrom(_) => {
+            StmtBody::ComeFrom(_) | StmtBody::NextFrom(_) => {
                 // nothing to do here
             }
             StmtBody::Dim(ref var, ref exprs) => {
@@ -227,12 +230,13 @@ impl Generator {
                 w!(self.o, 20; "let (old_pctr, comefrom, label) = \
                    pop_jumps(&mut jumps, val, true, {})?.expect(\"uh oh\");", self.line);
                 if self.program.uses_complex_comefrom {
-                    self.gen_comefrom_check("comefrom", "label")?;
+                    self.gen_comefrom_check("comefrom", "label", "old_pctr")?;
                 } else {
                     // XXX: chance check missing here
                     w!(self.o, 20; "if let Some(next) = comefrom {{
-                        if abstain[next] == 0 {{
-                            pctr = next;
+                        if abstain[next] == 0 {{");
+                    self.gen_nextfrom_check("next", "old_pctr")?;
+                    w!(self.o, 20; "pctr = next;
                             continue;
                         }}
                     }}");
@@ -289,24 +293,23 @@ impl Generator {
                             w!(self.o, 20; "{}.readout(&mut stdout, &mut last_out, {})?;",
                                Generator::get_varname(var), self.line);
                         }
-                        Expr::Var(_) => {
+                        Expr::Num(_, v) => {
+                            w!(self.o, 20; "write_number(&mut stdout, {}u32, {})?;", v, self.line);
+                        }
+                        _ => {
                             self.gen_eval_expr(expr)?;
                             w!(self.o, 20; "write_number(&mut stdout, val, {})?;", self.line);
                         }
-                        Expr::Num(_, v) => {
-                            w!(self.o, 20; "write_number(&mut stdout, {}, {})?;", v, self.line);
-                        }
-                        _ => return IE994.err_with(None, self.line),
                     };
                 }
             }
             StmtBody::WriteIn(ref vars) => {
                 for var in vars {
                     if var.is_dim() {
-                        w!(self.o, 20; "{}.writein(&mut last_in, {})?;",
+                        w!(self.o, 20; "{}.writein(&mut stdin, &mut last_in, {})?;",
                            Generator::get_varname(var), self.line);
                     } else {
-                        w!(self.o, 20; "let val = read_number({})?;",
+                        w!(self.o, 20; "let val: u32 = read_number(&mut stdin, {})?;",
                            self.line);
                         self.gen_assign(var)?;
                     }
@@ -326,13 +329,16 @@ impl Generator {
     }
 
     /// Check for COME FROMs if the program uses computed COME FROM.
-    fn gen_comefrom_check(&mut self, cand1: &str, label: &str) -> WRes {
+    fn gen_comefrom_check(&mut self, cand1: &str, label: &str, origin: &str) -> WRes {
         w!(self.o, 20; "let mut candidates = vec![];
                     if let Some(c) = {} {{ candidates.push(c); }}", cand1);
         let program = self.program.clone();
         for (i, stmt) in program.stmts.iter().enumerate() {
             if let StmtBody::ComeFrom(ComeFrom::Expr(ref e)) = stmt.body {
                 self.gen_eval_expr(e)?;
+                w!(self.o, 20; "if {} > 0 && val > 0xFFFF {{
+                        return err::IE275.err_with(None, {});
+                    }}", label, self.line);
                 w!(self.o, 20; "if val == {} && {} > 0 {{ candidates.push({}); }}",
                    label, label, i);
             }
@@ -341,12 +347,37 @@ impl Generator {
            self.line);
         // XXX: chance check missing here
         w!(self.o, 20; "if candidates.len() == 1 && abstain[candidates[0]] == 0 {{");
+        self.gen_nextfrom_check("candidates[0]", origin)?;
         w!(self.o, 24; "pctr = candidates[0];");
         w!(self.o, 24; "continue;");
         w!(self.o, 20; "}}");
         Ok(())
     }
 
+    /// Push the origin line onto the NEXT stack, for NEXT FROM.
+    fn gen_nextfrom_push(&mut self, origin: &str) -> WRes {
+        w!(self.o, 20; "if jumps.len() >= 80 {{
+                        return err::IE123.err_with(None, {});
+                    }}
+                    jumps.push(({}, None, 0));", self.line, origin);
+        Ok(())
+    }
+
+    /// Push the origin line onto the NEXT stack if the COME FROM statement about
+    /// to be jumped to, determined at runtime, is a NEXT FROM.
+    fn gen_nextfrom_check(&mut self, target: &str, origin: &str) -> WRes {
+        let nextfroms = self.program.stmts.iter().enumerate()
+            .filter(|&(_, stmt)| matches!(stmt.body, StmtBody::NextFrom(_)))
+            .map(|(i, _)| i.to_string())
+            .collect::<Vec<_>>();
+        if !nextfroms.is_empty() {
+            w!(self.o, 20; "if [{}].contains(&{}) {{", nextfroms.join(", "), target);
+            self.gen_nextfrom_push(origin)?;
+            w!(self.o, 20; "}}");
+        }
+        Ok(())
+    }
+
     /// Get the Rust name of the given variable reference.
     fn get_varname(var: &Var) -> String {
         match *var {
@@ -411,7 +442,11 @@ impl Generator {
     /// Helper for ABSTAIN.
     fn gen_abstain(&mut self, what: &Abstain, gen: &dyn Fn(String) -> String) -> WRes {
         if let Abstain::Label(lbl) = *what {
-            let idx = self.program.labels[&lbl];
+            // a label that does not exist has nothing to abstain from
+            let idx = match self.program.labels.get(&lbl) {
+                Some(&idx) => idx,
+                None => return Ok(()),
+            };
             w!(self.o, 20; "abstain[{}] = {};", idx, gen(format!("(abstain[{}] as u32)", idx)));
         } else {
             for (i, stype) in self.program.stmt_types.iter().enumerate() {
@@ -454,29 +489,19 @@ impl Generator {
             },
             Expr::Var(ref var) => self.gen_lookup(var, astype)?,
             Expr::Mingle(ref vx, ref wx) => {
-                w!(self.o; "mingle(");
-                if let Expr::Num(_, n) = **vx {
-                    if n > (u16::MAX as u32) {
-                        return IE533.err_with(None, self.line);
+                // constant operands can be checked right away
+                for x in &[vx, wx] {
+                    if let Expr::Num(_, n) = ***x {
+                        if n > (u16::MAX as u32) {
+                            return IE533.err_with(None, self.line);
+                        }
                     }
-                    self.gen_eval(vx, "")?;
-                } else {
-                    w!(self.o; "check_ovf(");
-                    self.gen_eval(vx, "")?;
-                    w!(self.o; ", {})?", self.line);
                 }
+                w!(self.o; "mingle(");
+                self.gen_eval(vx, "")?;
                 w!(self.o; ", ");
-                if let Expr::Num(_, n) = **wx {
-                    if n > (u16::MAX as u32) {
-                        return IE533.err_with(None, self.line);
-                    }
-                    self.gen_eval(wx, "")?;
-                } else {
-                    w!(self.o; "check_ovf(");
-                    self.gen_eval(wx, "")?;
-                    w!(self.o; ", {})?", self.line);
-                }
-                w!(self.o; "){}", astype);
+                self.gen_eval(wx, "")?;
+                w!(self.o; ", {})?{}", self.line, astype);
             }
             Expr::Select(vtype, ref vx, ref wx) => {
                 w!(self.o; "select(");
@@ -523,8 +548,8 @@ impl Generator {
             Expr::RsAnd(ref vx, ref wx) => self.gen_binop(vx, wx, "&", astype)?,
             Expr::RsOr(ref vx, ref wx) => self.gen_binop(vx, wx, "|", astype)?,
             Expr::RsXor(ref vx, ref wx) => self.gen_binop(vx, wx, "^", astype)?,
-            Expr::RsRshift(ref vx, ref wx) => self.gen_binop(vx, wx, ">>", astype)?,
-            Expr::RsLshift(ref vx, ref wx) => self.gen_binop_extrapar(vx, wx, "<<", astype)?,
+            Expr::RsRshift(ref vx, ref wx) => self.gen_shift(vx, wx, "checked_shr", astype)?,
+            Expr::RsLshift(ref vx, ref wx) => self.gen_shift(vx, wx, "checked_shl", astype)?,
             // Expr::RsEqual(ref vx, ref wx) => self.gen_binop(
             //     vx, wx, "==", if astype == "" { " as u32" } else { astype })?,
             Expr::RsNotEqual(ref vx, ref wx) => self.gen_binop(
@@ -544,12 +569,13 @@ impl Generator {
         Ok(())
     }
 
-    fn gen_binop_extrapar(&mut self, vx: &Expr, wx: &Expr, op: &str, astype: &str) -> WRes {
-        w!(self.o; "((");
+    /// Shifting by 32 or more gives zero, see `Expr::RsRshift`.
+    fn gen_shift(&mut self, vx: &Expr, wx: &Expr, method: &str, astype: &str) -> WRes {
+        w!(self.o; "(((");
         self.gen_eval(vx, "")?;
-        w!(self.o; ") {} (", op);
+        w!(self.o; ") as u32).{}(", method);
         self.gen_eval(wx, "")?;
-        w!(self.o; ")){}", astype);
+        w!(self.o; ").unwrap_or(0)){}", astype);
         Ok(())
     }
 
@@ -602,7 +628,9 @@ impl Generator {
         let vars = &self.program.var_info;
         // program counter
         w!(self.o, 4; "let mut pctr: usize = 0;");
-        // output stream
+        // input and output streams
+        w!(self.o, 4; "let stdin = std::io::stdin();");
+        w!(self.o, 4; "let mut stdin = stdin.lock();");
         w!(self.o, 4; "let mut stdout = std::io::stdout();");
         // NEXT stack (80 entries only)
         w!(self.o, 4; "let mut jumps: Vec<(usize, Option<usize>, u16)> = Vec::with_capacity(80);");
@@ -625,7 +653,8 @@ impl Generator {
             w!(self.o, 4; "let mut b{}: Bind<Array<u32>> = Bind::new(Array::empty());", i);
         }
         // list of abstention state for each statement, can initially be 0 or 1
-        w!(self.o, 4; "let mut abstain = [");
+        // (with the type given, since an empty program gives an empty list)
+        w!(self.o, 4; "let mut abstain: [u32; {}] = [", self.program.stmts.len());
         for (i, stmt) in self.program.stmts.iter().enumerate() {
             if i % 24 == 0 {
                 w!(self.o, 7; "");
@@ -649,7 +678,7 @@ impl Generator {
 
     fn gen_loop_footer(&mut self) -> WRes {
         w!(self.o, 12; "_ => {{");
-        if let StmtBody::TryAgain = self.program.stmts[self.program.stmts.len() - 1].body {
+        if let Some(StmtBody::TryAgain) = self.program.stmts.last().map(|stmt| &stmt.body) {
             w!(self.o, 16; "break;");
         } else {
             w!(self.o, 16; "return err::IE633.err();");
@@ -48,6 +48,9 @@ pub struct RtError {
     error:  &'static ErrDesc,
     addstr: Option<String>,
     lineno: usize,
+    colno:  usize,
+    /// Source line of the failing statement and its position on the line.
+    stmt:   (usize, usize),
 }
 
 impl RtError {
@@ -55,6 +58,17 @@ impl RtError {
         self.lineno = lineno;
     }
 
+    /// Set the column within the line given by `set_line`, 0 if unknown.
+    pub fn set_col(&mut self, colno: usize) {
+        self.colno = colno;
+    }
+
+    /// Set the source line of the failing statement, and its position among the
+    /// statements on that line (from 1, 0 if unknown).
+    pub fn set_stmt(&mut self, srcline: usize, stmt_on_line: usize) {
+        self.stmt = (srcline, stmt_on_line);
+    }
+
     pub fn to_string(&self) -> String {
         let mut msg = String::from(self.error.msg);
         if let Some(ref s) = self.addstr {
@@ -69,6 +83,46 @@ impl RtError {
                 self.error.num, msg, lineinfo)
     }
 
+    /// Like `to_string`, but if the column is known, also show the "on the way
+    /// to" source line with a marker below the statement.  Additional info that
+    /// the message has no place for (like the NEXT stack for IE123) is shown too,
+    /// as is which statement failed, if known.
+    pub fn display_with_source(&self, code: &str) -> String {
+        let mut msg = self.to_string();
+        if let Some(ref s) = self.addstr {
+            if !self.error.msg.contains("{}") {
+                msg.push_str(&format!("\t{}\n", s));
+            }
+        }
+        if self.stmt.1 > 0 {
+            msg.push_str(&format!("\tin the {} statement on line {}\n",
+                                  ordinal(self.stmt.1), self.stmt.0));
+        }
+        if self.colno > 0 && self.lineno > 0 {
+            if let Some(line) = code.lines().nth(self.lineno - 1) {
+                // keep tabs so that the marker lines up
+                let indent: String = line.chars().take(self.colno - 1)
+                                         .map(|c| if c == '\t' { c } else { ' ' }).collect();
+                msg.push_str(&format!("{}\n{}^\n", line, indent));
+            }
+        }
+        msg
+    }
+
+    /// Whether execution can sensibly go on with the next statement after this
+    /// error, which is the case for bad input: unknown number words (IE579), and
+    /// numbers too large for 32 bits (IE533) or the variable (IE275) if they were
+    /// being read in, not computed.
+    pub fn is_recoverable(&self, reading_input: bool) -> bool {
+        self.error.num == IE579.num ||
+            (reading_input && (self.error.num == IE533.num || self.error.num == IE275.num))
+    }
+
+    /// Get the process exit status to use when aborting with this error.
+    pub fn exit_code(&self) -> i32 {
+        EXIT_CODES.iter().find(|&&(e, _)| e == self.error).map_or(1, |&(_, code)| code)
+    }
+
     pub fn short_string(&self) -> &str {
         match self.addstr {
             Some(ref s) => s,
@@ -82,6 +136,18 @@ impl RtError {
     }
 }
 
+/// English ordinal of a number: 1st, 2nd, 3rd, 4th, ..., 11th, ..., 21st.
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
 impl From<io::Error> for RtError {
     fn from(_: io::Error) -> RtError {
         IE888.new(None, 0)
@@ -89,16 +155,27 @@ impl From<io::Error> for RtError {
 }
 
 impl ErrDesc {
+    #[allow(dead_code)]  // for tooling
+    pub fn num(&self) -> u16 {
+        self.num
+    }
+
+    #[allow(dead_code)]  // for tooling
+    pub fn msg(&self) -> &'static str {
+        self.msg
+    }
+
     pub fn new(&'static self, addstr: Option<String>, lineno: usize) -> RtError {
-        RtError { error: self, addstr, lineno }
+        RtError { error: self, addstr, lineno, colno: 0, stmt: (0, 0) }
     }
 
     pub fn err<T>(&'static self) -> Result<T, RtError> {
-        Err(RtError { error: self, addstr: None, lineno: 0 })
+        Err(RtError { error: self, addstr: None, lineno: 0, colno: 0, stmt: (0, 0) })
     }
 
     pub fn err_with<T>(&'static self, addstr: Option<&str>, lineno: usize) -> Result<T, RtError> {
-        Err(RtError { error: self, addstr: addstr.map(|v| v.into()), lineno })
+        Err(RtError { error: self, addstr: addstr.map(|v| v.into()), lineno, colno: 0,
+                      stmt: (0, 0) })
     }
 }
 
@@ -173,6 +250,13 @@ pub static IE200: ErrDesc = ErrDesc {
     way: None,
 };
 
+/* An assignment to a variable that the dialect makes read-only. */
+pub static IE212: ErrDesc = ErrDesc {
+    num: 212,
+    msg: "HANDS OFF {}, IT IS NOT YOURS TO CHANGE",
+    way: None,
+};
+
 /* An attempt has been made to give an array a dimension of zero. */
 pub static IE240: ErrDesc = ErrDesc {
     num: 240,
@@ -252,7 +336,8 @@ pub static IE621: ErrDesc = ErrDesc {
     way: None,
 };
 
-/* Input data is invalid. */
+/* A RESUME statement tried to go back more entries than the NEXT
+ * stack holds. */
 pub static IE632: ErrDesc = ErrDesc {
     num: 632,
     msg: "THE NEXT STACK RUPTURES.  ALL DIE.  OH, THE EMBARRASSMENT!",
@@ -321,3 +406,37 @@ pub static IE998: ErrDesc = ErrDesc {
     msg: "EXCUSE ME",
     way: Some("YOU MUST HAVE ME CONFUSED WITH SOME OTHER COMPILER"),
 };
+
+
+/// All errors above, ordered by code.
+static ALL_CODES: [&ErrDesc; 32] = [
+    &IE000, &IE017, &IE079, &IE099, &IE123, &IE129, &IE139, &IE182, &IE197, &IE200, &IE212, &IE240,
+    &IE241, &IE252, &IE275, &IE436, &IE444, &IE533, &IE555, &IE562, &IE579, &IE621, &IE632, &IE633,
+    &IE666, &IE774, &IE777, &IE888, &IE990, &IE993, &IE994, &IE998,
+];
+
+/// Process exit statuses (from BSD's sysexits.h) for families of errors that
+/// scripts may want to tell apart.  All other errors exit with status 1.
+static EXIT_CODES: [(&ErrDesc, i32); 9] = [
+    (&IE990, 64),  // EX_USAGE: bad command line
+    (&IE562, 65),  // EX_DATAERR: bad program input
+    (&IE579, 65),
+    (&IE777, 66),  // EX_NOINPUT: no usable source file
+    (&IE998, 66),
+    (&IE666, 70),  // EX_SOFTWARE: internal errors, including of rustc
+    (&IE994, 70),
+    (&IE252, 74),  // EX_IOERR: could not write output
+    (&IE888, 74),
+];
+
+/// Get all known errors, ordered by code.
+#[allow(dead_code)]  // for tooling
+pub fn all_codes() -> &'static [&'static ErrDesc] {
+    &ALL_CODES
+}
+
+/// Look up an error by its code.
+#[allow(dead_code)]  // for tooling
+pub fn by_code(num: u16) -> Option<&'static ErrDesc> {
+    ALL_CODES.iter().find(|e| e.num == num).copied()
+}
@@ -20,14 +20,20 @@
 /// The evaluator is used when rick is called with `-i`, or when the compiler generates
 /// the output while compiling (in the constant-output case).
 
-use std::fmt::{Debug, Display};
-use std::io::Write;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{Debug, Display, Error, Formatter};
+use std::io::{self, BufRead, Cursor, Read, Write};
+use std::mem;
 use std::u16;
 
-use crate::err::{Res, IE123, IE129, IE252, IE275, IE555, IE633, IE774, IE994};
-use crate::ast::{self, Program, Stmt, StmtBody, ComeFrom, Expr, Var, VType};
-use crate::stdops::{Bind, Array, write_number, read_number, check_chance, check_ovf, pop_jumps,
-                    get_random_seed, mingle, select, and_16, and_32, or_16, or_32, xor_16, xor_32};
+use crate::err::{Res, RtError, IE123, IE129, IE139, IE212, IE241, IE252, IE275, IE555, IE633, IE774,
+                 IE994};
+use crate::ast::{self, Program, Stmt, StmtBody, ComeFrom, Expr, Label, Var, VType};
+use crate::stdops::{Bind, Array, push_roman, tape_out, to_roman, write_number, write_roman_row,
+                    write_bytes, read_number, read_number_or_digits, read_number_radix,
+                    read_number_saturating, read_roman, read_byte, check_chance, pop_jumps,
+                    get_random_seed, mingle_with, select, and_16, and_32, or_16, or_32,
+                    xor_16, xor_32, MingleOrder};
 
 
 /// Represents a value (either 16-bit or 32-bit) at runtime.
@@ -74,12 +80,129 @@ impl Val {
     }
 }
 
+/// Shows the value in decimal with its type, like `42:I16`, for debugging (the
+/// program's own output is in Roman numerals, see `stdops::to_roman`).
+impl Display for Val {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        match *self {
+            Val::I16(v) => write!(fmt, "{}:I16", v),
+            Val::I32(v) => write!(fmt, "{}:I32", v),
+        }
+    }
+}
+
+/// How numbers written by READ OUT are separated from each other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RomanSep {
+    /// Every number gets its own two lines (the default).
+    Newline,
+    /// Numbers are put side by side, separated by a space.
+    Space,
+    /// Numbers are put side by side without any separation.
+    None,
+    /// Numbers of one READ OUT are put side by side, separated by a space;
+    /// every READ OUT gets its own two lines.
+    Batch,
+}
+
+/// How WRITE IN reads values for scalar variables and array elements.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputMode {
+    /// Numbers spelled out in digit words (the default).
+    Number,
+    /// Like `Number`, but plain decimal digits are accepted as well.
+    Digits,
+    /// Like `Number`, but a leading HEX or OCTAL word selects another radix.
+    Radix,
+    /// Like `Number`, but numbers too large for 32 bits read as the largest
+    /// 32-bit number instead of failing.
+    Saturating,
+    /// Single bytes, with 256 on EOF.
+    Byte,
+    /// Roman numerals on two lines, in the format of READ OUT.
+    Roman,
+}
+
+/// Numbers below this are kept in Roman format once printed, so that printing
+/// them again needs no conversion.
+const ROMAN_CACHE_SIZE: usize = 4096;
+
+/// An item of the program's output, as recorded with `Eval::record_output`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputItem {
+    /// A number written by READ OUT, in Roman numerals.
+    Number(u32),
+    /// A byte written by READ OUT of an array, or by the constant output that
+    /// the optimizer may have replaced the program with.
+    Byte(u8),
+}
+
+/// A statement reached during execution, as recorded with `Eval::record_trace`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TraceStep {
+    /// Index of the statement.
+    pub pctr: usize,
+    /// Outcome of the statement's % chance, if it had to be rolled.
+    pub chance: Option<bool>,
+    /// Outcome of the % chance of a COME FROM catching it, if rolled.
+    pub comefrom: Option<bool>,
+}
+
+/// How a program run by `Eval::eval` ended.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Termination {
+    /// With GIVE UP.
+    GaveUp,
+    /// Past the last statement, after a TRY AGAIN or with `set_fall_off_ok`.
+    FellOffEnd,
+    /// At the statement limit set with `set_max_stmts`.
+    LimitReached,
+}
+
+/// What a successful `Eval::eval` returns.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Outcome {
+    /// Number of statements, including those abstained from or skipped.
+    pub stmts: usize,
+    /// How the program ended.
+    pub termination: Termination,
+}
+
+/// Hooks called by the evaluator at interesting points of the execution, for
+/// visualizers and similar tools.  All of them do nothing by default.
+///
+/// Statements are given as indices into the program's statements, variables
+/// with their numbers from the source.
+pub trait EvalHooks {
+    /// A statement is about to be executed: it is not abstained from, and its
+    /// chance came up.
+    fn on_stmt(&mut self, _pctr: usize, _stmt: &Stmt) { }
+    /// A variable or array element (with constant subscripts) was assigned,
+    /// also if it is IGNOREd and keeps its value.
+    fn on_assign(&mut self, _var: &Var, _val: &Val) { }
+    /// Control goes from one statement to another, by NEXT, RESUME or TRY AGAIN.
+    fn on_jump(&mut self, _from: usize, _to: usize) { }
+    /// Control goes from a statement to a COME FROM or NEXT FROM catching it.
+    fn on_comefrom(&mut self, _from: usize, _to: usize) { }
+    /// Output was written.
+    fn on_output(&mut self, _bytes: &[u8]) { }
+}
+
 /// The state of the interpreter's evaluator.
+///
+/// The program is only borrowed and never modified, and the I/O streams must be
+/// `Send`, so that an evaluator is `Send` too.  Since it borrows the program
+/// and the streams, it can be moved to scoped threads (`std::thread::scope`)
+/// that do not outlive them, but not to `std::thread::spawn`.  For that, share
+/// the program in an `Arc` and call `Eval::run` in the new thread, which owns
+/// its input and output.
 pub struct Eval<'a> {
     /// Program to execute.
     program: &'a Program,
+    /// Stream to use for reading input.
+    stdin: &'a mut (dyn BufRead + Send),
     /// Stream to use for printing output.
-    stdout: &'a mut dyn Write,
+    stdout: CountingOutput<'a>,
     /// Whether to print debugging output during execution.
     debug: bool,
     /// Variable bindings for the four types of variables.
@@ -87,17 +210,70 @@ pub struct Eval<'a> {
     twospot: Vec<Bind<u32>>,
     tail: Vec<Bind<Array<u16>>>,
     hybrid: Vec<Bind<Array<u32>>>,
-    /// The infamous NEXT stack, capable of holding 80 elements.
-    jumps: Vec<ast::LogLine>,
+    /// The infamous NEXT stack, capable of holding 80 elements.  Next to the
+    /// line to return to, it records whether COME FROMs are checked for that
+    /// line when returning (not if it was pushed by NEXT FROM).
+    jumps: Vec<(ast::LogLine, bool)>,
     /// Abstain counter for each statement.
     abstain: Vec<u32>,
     /// Binary I/O "tape" state.
     last_in: u8,
     last_out: u8,
-    /// Random number generator state.
+    /// Input mode for scalar WRITE IN.
+    input_mode: InputMode,
+    /// Whether redimensioning an array keeps its contents.
+    preserve_on_redim: bool,
+    /// Whether mingle operands are cut to 16 bits, instead of being an error.
+    truncate_mingle: bool,
+    /// Which mingle operand goes into the higher bit of each pair.
+    mingle_order: MingleOrder,
+    /// Maximum number of elements an array may be dimensioned with.
+    max_elems: usize,
+    /// Whether falling off the end of the program ends it like GIVE UP.
+    fall_off_ok: bool,
+    /// Separation of numbers, and the overbar and digit lines of the numbers
+    /// collected so far if they are put side by side.
+    roman_sep: RomanSep,
+    roman_row: Option<(String, String)>,
+    /// Width the two lines of every number are padded to, 0 for no padding.
+    roman_width: usize,
+    /// Roman format of the small numbers printed so far (empty if not yet).
+    roman_cache: Vec<String>,
+    /// Random number generator state.  Every evaluator has its own, there is
+    /// no global generator that seeding could disturb.
     rand_st: u32,
     /// Counts the number of executed statements.
     stmt_ctr: usize,
+    /// Number of statements after which the program is stopped.
+    max_stmts: usize,
+    /// Counts only statements that were not abstained from or skipped by chance.
+    executed_ctr: usize,
+    /// Highest number of entries the NEXT stack had so far.
+    max_jump_depth: usize,
+    /// If recording, the labels jumped to by NEXT so far.
+    next_targets: Option<HashSet<Label>>,
+    /// If recording, everything output so far.
+    output_log: Option<Vec<OutputItem>>,
+    /// If recording, the statements reached so far.
+    trace: Option<Vec<TraceStep>>,
+    /// If replaying, the recorded statements still to come.
+    replay: Option<VecDeque<TraceStep>>,
+    /// If replaying, the recorded statement currently executed.
+    replay_step: Option<TraceStep>,
+    /// If set, recoverable errors are written here and execution continues.
+    diagnostics: Option<&'a mut (dyn Write + Send)>,
+    /// Number of recoverable errors reported so far.
+    recovered_ctr: usize,
+    /// If set, a summary of the run is written here when the program gives up.
+    summary: Option<&'a mut (dyn Write + Send)>,
+    /// Variables (as `Var::unique` keys) that may not be assigned to.
+    read_only: Vec<(u8, usize)>,
+    /// Index of the statement the next `eval` or `step` starts with.
+    start: usize,
+    /// For each gerund abstained from or reinstated so far, the resulting
+    /// abstention state of a statement that was enabled or disabled at first,
+    /// so that `extend` can apply the same to new statements.
+    gerund_abstain: Vec<(ast::Abstain, [u32; 2])>,
 }
 
 /// Represents the control flow effect of an executed statement.
@@ -106,18 +282,26 @@ enum Flow {
     Next,
     /// jump around, from DO ... NEXT
     Jump(usize),
-    /// jump back, from RESUME
-    Back(usize),
+    /// jump back, from RESUME, and whether to check for COME FROMs there
+    Back(usize, bool),
     /// start from the first statement, from TRY AGAIN
     FromTop,
     /// end the program, from GIVE UP
     End,
 }
 
+/// Represents where execution goes on after a statement.
+enum Step {
+    /// with the statement at this index
+    At(usize),
+    /// nowhere, the program ended
+    Done(Termination),
+}
+
 impl<'a> Eval<'a> {
     /// Construct a new evaluator.
-    pub fn new(program: &'a Program, stdout: &'a mut dyn Write, debug: bool,
-               random: bool) -> Eval<'a> {
+    pub fn new(program: &'a Program, stdin: &'a mut (dyn BufRead + Send),
+               stdout: &'a mut (dyn Write + Send), debug: bool, random: bool) -> Eval<'a> {
         let abs = program.stmts.iter().map(|stmt| stmt.props.disabled as u32).collect();
         let nvars = (program.var_info.0.len(),
                      program.var_info.1.len(),
@@ -125,7 +309,9 @@ impl<'a> Eval<'a> {
                      program.var_info.3.len());
         Eval {
             program,
-            stdout,
+            stdin,
+            stdout: CountingOutput { inner: stdout, count: 0, limit: usize::MAX,
+                                     lines: 0, line_limit: usize::MAX, hooks: None },
             debug,
             spot:     vec![Bind::new(0); nvars.0],
             twospot:  vec![Bind::new(0); nvars.1],
@@ -136,107 +322,580 @@ impl<'a> Eval<'a> {
             abstain:  abs,
             last_in:  0,
             last_out: 0,
+            input_mode: InputMode::Number,
+            preserve_on_redim: false,
+            truncate_mingle: false,
+            mingle_order: MingleOrder::FirstHigh,
+            max_elems: usize::MAX,
+            fall_off_ok: false,
+            roman_sep: RomanSep::Newline,
+            roman_row: None,
+            roman_width: 0,
+            roman_cache: Vec::new(),
             stmt_ctr: 0,
+            max_stmts: usize::MAX,
+            executed_ctr: 0,
+            max_jump_depth: 0,
+            next_targets: None,
+            output_log: None,
+            trace: None,
+            replay: None,
+            replay_step: None,
+            diagnostics: None,
+            recovered_ctr: 0,
+            summary: None,
+            read_only: Vec::new(),
+            start:    0,
+            gerund_abstain: Vec::new(),
+        }
+    }
+
+    /// Seed the random number generator, for reproducible runs.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rand_st = seed as u32;
+    }
+
+    /// Select how numbers are separated on output.
+    pub fn set_roman_sep(&mut self, sep: RomanSep) {
+        self.roman_sep = sep;
+    }
+
+    /// Pad the overbar and digit lines of every number written to at least
+    /// `width` characters, so that numbers line up in columns.  Zero (the
+    /// default) means no padding.
+    pub fn set_roman_width(&mut self, width: usize) {
+        self.roman_width = width;
+    }
+
+    /// Select how scalar WRITE IN reads its input.
+    pub fn set_input_mode(&mut self, mode: InputMode) {
+        self.input_mode = mode;
+    }
+
+    /// Select whether redimensioning an array keeps the elements whose subscripts
+    /// stay valid, instead of resetting all of them.  With a different number of
+    /// dimensions no subscripts stay valid, so the array is still reset then.
+    pub fn set_preserve_on_redim(&mut self, preserve: bool) {
+        self.preserve_on_redim = preserve;
+    }
+
+    /// Select whether mingle operands that do not fit in 16 bits are silently
+    /// cut to their lower 16 bits, as some dialects do, instead of being an
+    /// error (IE533).
+    pub fn set_truncate_mingle(&mut self, truncate: bool) {
+        self.truncate_mingle = truncate;
+    }
+
+    /// Select which mingle operand ends up in the higher bit of each pair, for
+    /// programs written for implementations with the other convention.  The
+    /// optimizer assumes the default order, so such programs must not be
+    /// optimized.
+    pub fn set_mingle_order(&mut self, order: MingleOrder) {
+        self.mingle_order = order;
+    }
+
+    /// Limit the number of elements in an array, so that untrusted programs
+    /// cannot exhaust memory.  Larger dimensions result in an error.
+    pub fn set_max_elems(&mut self, max: usize) {
+        self.max_elems = max;
+    }
+
+    /// Select whether falling off the end of the program, without GIVE UP,
+    /// simply ends it, as some dialects allow, instead of being an error
+    /// (IE633).  The program is then considered not to have given up.
+    pub fn set_fall_off_ok(&mut self, ok: bool) {
+        self.fall_off_ok = ok;
+    }
+
+    /// Limit the number of bytes the program may output, so that runaway
+    /// programs stop early.  Output beyond the limit is an error (IE252).
+    pub fn set_max_output(&mut self, max: usize) {
+        self.stdout.limit = max;
+    }
+
+    /// Limit the number of lines the program may output, counted as newlines
+    /// written.  Since every Roman numeral takes two lines, this catches
+    /// runaway numeric output that a byte limit lets through when the numbers
+    /// are small.  Output beyond the limit is an error (IE252).
+    pub fn set_max_output_lines(&mut self, max: usize) {
+        self.stdout.line_limit = max;
+    }
+
+    /// Stop the program after `max` statements (counted like the number `eval`
+    /// returns).  This is not an error: `eval` reports `LimitReached`.
+    pub fn set_max_stmts(&mut self, max: usize) {
+        self.max_stmts = max;
+    }
+
+    /// Select lenient mode: recoverable errors, such as unparseable input, are
+    /// reported to `sink` and execution continues with the next statement.
+    pub fn set_lenient(&mut self, sink: &'a mut (dyn Write + Send)) {
+        self.diagnostics = Some(sink);
+    }
+
+    /// Call `hooks` while running, see `EvalHooks`.
+    #[allow(dead_code)]  // for visualizers
+    pub fn set_hooks(&mut self, hooks: &'a mut (dyn EvalHooks + Send)) {
+        self.stdout.hooks = Some(hooks);
+    }
+
+    /// Write a short summary of the run to `sink` when the program gives up: the
+    /// statement counts, the maximum NEXT stack depth, the number of splats
+    /// (statements that fail when run, so none of them was), the number of
+    /// recovered errors, and the number of bytes output.
+    pub fn set_summary(&mut self, sink: &'a mut (dyn Write + Send)) {
+        self.summary = Some(sink);
+    }
+
+    /// Make variables read-only, for dialects with special registers.  They are
+    /// given with their numbers from the source; subscripts are ignored.
+    pub fn set_read_only(&mut self, vars: &[Var]) {
+        for var in vars {
+            // variables not used by the program need no protection
+            if let Some(var) = self.program_var(var) {
+                self.read_only.push(var.unique());
+            }
         }
     }
 
-    /// Interpret the program.  Returns either the number of executed statements,
-    /// or an error (RtError).
-    pub fn eval(&mut self) -> Res<usize> {
-        let mut pctr = 0;  // index of current statement
+    /// Preset a variable or array element before running, as if the program
+    /// had assigned it.  The variable is given with its number from the source,
+    /// and constant subscripts; arrays must be dimensioned first with
+    /// `preset_dims`.  Variables not used by the program are ignored.
+    #[allow(dead_code)]  // for testing program fragments
+    pub fn preset(&mut self, var: &Var, val: Val) -> Res<()> {
+        match self.program_var(var) {
+            Some(var) => self.assign(&var, val),
+            None => Ok(()),
+        }
+    }
+
+    /// Preset the dimensions of an array before running, see `preset`.
+    #[allow(dead_code)]  // for testing program fragments
+    pub fn preset_dims(&mut self, var: &Var, dims: &[usize]) -> Res<()> {
+        let (dims, preserve, max) = (dims.to_vec(), self.preserve_on_redim, self.max_elems);
+        match self.program_var(var) {
+            Some(Var::A16(n, _)) => self.tail[n].dimension_capped(dims, preserve, max, 0),
+            Some(Var::A32(n, _)) => self.hybrid[n].dimension_capped(dims, preserve, max, 0),
+            Some(_) => IE241.err(),
+            None => Ok(()),
+        }
+    }
+
+    /// Translate a variable with its number from the source into the one used
+    /// by the program, if the program uses it at all.
+    fn program_var(&self, var: &Var) -> Option<Var> {
+        let (kind, orig) = var.unique();
+        let infos = match kind {
+            0 => &self.program.var_info.0,
+            1 => &self.program.var_info.1,
+            2 => &self.program.var_info.2,
+            _ => &self.program.var_info.3,
+        };
+        infos.iter().position(|info| info.orig == orig).map(|n| {
+            let mut var = var.clone();
+            var.rename(n);
+            var
+        })
+    }
+
+    /// Continue with a longer version of the program, as built by
+    /// `Parser::concat` from the program so far and some more statements, for
+    /// interactive shells.  Variables, the NEXT stack and abstentions are kept,
+    /// and the next `eval` starts with the first new statement.  Abstentions by
+    /// gerund so far also apply to the new statements of that type.
+    #[allow(dead_code)]  // for interactive shells
+    pub fn extend(&mut self, program: &'a Program) {
+        let old = self.program.stmts.len();
+        let gerunds = &self.gerund_abstain;
+        let new = program.stmts[old..].iter().zip(&program.stmt_types[old..]);
+        self.abstain.extend(new.map(|(stmt, stype)| {
+            let initial = stmt.props.disabled as usize;
+            gerunds.iter().find(|(gerund, _)| gerund == stype)
+                          .map_or(initial as u32, |(_, states)| states[initial])
+        }));
+        self.spot.resize(program.var_info.0.len(), Bind::new(0));
+        self.twospot.resize(program.var_info.1.len(), Bind::new(0));
+        self.tail.resize(program.var_info.2.len(), Bind::new(Array::empty()));
+        self.hybrid.resize(program.var_info.3.len(), Bind::new(Array::empty()));
+        self.program = program;
+        self.start = old;
+    }
+
+    /// Number of statements actually executed so far, unlike the count returned
+    /// by `eval`, which includes abstained and skipped statements.
+    pub fn executed_count(&self) -> usize {
+        self.executed_ctr
+    }
+
+    /// Highest depth the NEXT stack reached so far, to see how close the
+    /// program came to the limit of 80 entries (IE123).
+    pub fn max_jump_depth(&self) -> usize {
+        self.max_jump_depth
+    }
+
+    /// Start recording the labels that NEXT statements jump to, for coverage of
+    /// the control flow.
+    #[allow(dead_code)]  // for coverage tools
+    pub fn record_next_targets(&mut self) {
+        self.next_targets.get_or_insert_with(HashSet::new);
+    }
+
+    /// The labels jumped to by NEXT so far, if recording.
+    #[allow(dead_code)]  // for coverage tools
+    pub fn next_targets(&self) -> Option<&HashSet<Label>> {
+        self.next_targets.as_ref()
+    }
+
+    /// Start recording the output item by item, in addition to writing it, so
+    /// that it can be checked without parsing Roman numerals.
+    #[allow(dead_code)]  // for testing programs
+    pub fn record_output(&mut self) {
+        self.output_log.get_or_insert_with(Vec::new);
+    }
+
+    /// The output so far, if recording.
+    #[allow(dead_code)]  // for testing programs
+    pub fn output_log(&self) -> Option<&[OutputItem]> {
+        self.output_log.as_deref()
+    }
+
+    /// Start recording a trace of the execution: every statement reached, with
+    /// the outcomes of the % chances rolled for it.  Replaying the trace gives
+    /// exactly the same run, independent of the random number generator.
+    #[allow(dead_code)]  // for replay and golden tests
+    pub fn record_trace(&mut self) {
+        self.trace.get_or_insert_with(Vec::new);
+    }
+
+    /// The trace so far, if recording.
+    #[allow(dead_code)]  // for replay and golden tests
+    pub fn trace(&self) -> Option<&[TraceStep]> {
+        self.trace.as_deref()
+    }
+
+    /// Replay a recorded trace: % chances come out as recorded, and the random
+    /// number generator is not used.  If the program goes another way than the
+    /// trace, that is an internal error (IE994).
+    #[allow(dead_code)]  // for replay and golden tests
+    pub fn set_replay(&mut self, trace: &[TraceStep]) {
+        self.replay = Some(trace.iter().cloned().collect());
+    }
+
+    /// Abstain from (if `on`) or reinstate the statements with a label or of a
+    /// type, as from a debugger.  Unlike ABSTAIN and REINSTATE in the program,
+    /// this sets the state outright instead of counting.  GIVE UP cannot be
+    /// abstained from, as usual, and a label that does not exist is an error
+    /// (IE139).
+    #[allow(dead_code)]  // for debuggers
+    pub fn set_abstain(&mut self, what: &ast::Abstain, on: bool) -> Res<()> {
+        if let ast::Abstain::Label(lbl) = *what {
+            if !self.program.labels.contains_key(&lbl) {
+                return IE139.err();
+            }
+        }
+        let state = on as u32;
+        self.abstain(what, &move |_| state);
+        Ok(())
+    }
+
+    /// Run a program to completion on the given input, and return its output
+    /// together with how it ended.  If no seed is given, the random number
+    /// generator is seeded randomly.
+    pub fn run(program: &Program, input: &[u8], seed: Option<u64>) -> Res<(Vec<u8>, Outcome)> {
+        let mut stdin = Cursor::new(input);
+        let mut stdout = Vec::new();
+        let mut eval = Eval::new(program, &mut stdin, &mut stdout, false, seed.is_none());
+        if let Some(seed) = seed {
+            eval.set_seed(seed);
+        }
+        let outcome = eval.eval()?;
+        Ok((stdout, outcome))
+    }
+
+    /// Interpret the program.  Returns either the number of executed statements
+    /// and how the program ended, or an error (RtError).
+    pub fn eval(&mut self) -> Res<Outcome> {
+        let res = self.eval_loop();
+        // output must not get lost, even on error
+        let flushed = self.flush();
+        res.and_then(|termination| flushed.and_then(|_| {
+            if termination == Termination::GaveUp {
+                self.write_summary()?;
+            }
+            Ok(Outcome { stmts: self.stmt_ctr, termination })
+        }))
+    }
+
+    /// Write out any pending output: numbers collected side by side, and
+    /// whatever the output stream buffers.  This is done at the end of `eval`,
+    /// also if the program ends with an error.
+    pub fn flush(&mut self) -> Res<()> {
+        self.flush_roman_row()?;
+        if self.stdout.flush().is_err() {
+            return IE252.err();
+        }
+        Ok(())
+    }
+
+    /// Execute a single statement (or skip it, if abstained from), where the
+    /// next `eval` would start.  Returns the index of the statement to execute
+    /// next, or None if the program has ended.  Output is not flushed.
+    #[allow(dead_code)]  // for debuggers
+    pub fn step(&mut self) -> Res<Option<usize>> {
+        match self.step_at(self.start)? {
+            Step::At(next) => {
+                self.start = next;
+                Ok(Some(next))
+            }
+            Step::Done(_) => {
+                self.start = 0;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Step until the value of a variable changes, and return the index of the
+    /// statement that changed it, or None if the program ended first.  The
+    /// variable is given with its number from the source, like for `preset`;
+    /// for a whole array, any change of an element or the dimensions counts.
+    #[allow(dead_code)]  // for debuggers
+    pub fn run_to_watch(&mut self, var: &Var) -> Res<Option<usize>> {
+        let var = match self.program_var(var) {
+            Some(var) => var,
+            // a variable the program does not use never changes
+            None => return Ok(None),
+        };
+        let mut before = self.watched(&var);
+        loop {
+            let pctr = self.start;
+            if self.step()?.is_none() {
+                return Ok(None);
+            }
+            let after = self.watched(&var);
+            if after != before {
+                return Ok(Some(pctr));
+            }
+            before = after;
+        }
+    }
+
+    /// The state of a watched variable: dimensions and elements for a whole
+    /// array, else just the value (None if it cannot be looked up).
+    fn watched(&self, var: &Var) -> Option<(Vec<usize>, Vec<Val>)> {
+        match *var {
+            Var::A16(n, ref subs) if subs.is_empty() => {
+                let arr = &self.tail[n].val;
+                Some((arr.dims().to_vec(), arr.iter().map(Val::I16).collect()))
+            }
+            Var::A32(n, ref subs) if subs.is_empty() => {
+                let arr = &self.hybrid[n].val;
+                Some((arr.dims().to_vec(), arr.iter().map(Val::I32).collect()))
+            }
+            _ => self.lookup(var).ok().map(|val| (vec![], vec![val])),
+        }
+    }
+
+    /// Write the summary of the run, if requested.
+    fn write_summary(&mut self) -> Res<()> {
         let program = self.program;
-        let nstmts = program.stmts.len();
+        if let Some(ref mut sink) = self.summary {
+            let splats = program.stmts.iter().filter(|s| matches!(s.body, StmtBody::Error(_)))
+                                             .count();
+            if writeln!(sink, "GIVEN UP after {} statements ({} executed), NEXT stack depth {}, \
+                               {} splats, {} errors recovered, {} bytes output",
+                        self.stmt_ctr, self.executed_ctr, self.max_jump_depth, splats,
+                        self.recovered_ctr, self.stdout.count).is_err() {
+                return IE252.err();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns how the program ended.
+    fn eval_loop(&mut self) -> Res<Termination> {
+        let mut pctr = mem::replace(&mut self.start, 0);  // index of current statement
         loop {
-            // check for falling off the end
-            if pctr >= nstmts {
-                // if the last statement was a TRY AGAIN, falling off the end is fine
-                if let StmtBody::TryAgain = program.stmts[program.stmts.len() - 1].body {
-                    break;
+            match self.step_at(pctr)? {
+                Step::At(next) => pctr = next,
+                Step::Done(termination) => return Ok(termination),
+            }
+        }
+    }
+
+    /// Execute (or skip) the statement at `pctr`, and determine where to go on.
+    fn step_at(&mut self, mut pctr: usize) -> Res<Step> {
+        let program = self.program;
+        // check for falling off the end
+        if pctr >= program.stmts.len() {
+            // if the last statement was a TRY AGAIN, falling off the end is fine
+            // (an empty program has no last statement, and falls off at once)
+            if let Some(StmtBody::TryAgain) = program.stmts.last().map(|stmt| &stmt.body) {
+                return Ok(Step::Done(Termination::FellOffEnd));
+            }
+            if self.fall_off_ok {
+                return Ok(Step::Done(Termination::FellOffEnd));
+            }
+            return IE633.err();
+        }
+        if self.stmt_ctr >= self.max_stmts {
+            return Ok(Step::Done(Termination::LimitReached));
+        }
+        self.stmt_ctr += 1;
+        let stmt = &program.stmts[pctr];
+        if let Some(ref mut replay) = self.replay {
+            match replay.pop_front() {
+                Some(step) if step.pctr == pctr => self.replay_step = Some(step),
+                _ => return IE994.err_with(None, stmt.props.onthewayto),
+            }
+        }
+        if let Some(ref mut trace) = self.trace {
+            trace.push(TraceStep { pctr, chance: None, comefrom: None });
+        }
+        // execute statement if not abstained
+        if self.abstain[pctr] == 0 {
+            // check execution chance
+            if self.roll_chance(stmt.props.chance, false, stmt.props.onthewayto)? {
+                self.executed_ctr += 1;
+                if let Some(hooks) = self.stdout.hooks.as_deref_mut() {
+                    hooks.on_stmt(pctr, stmt);
                 }
-                return IE633.err();
-            }
-            self.stmt_ctr += 1;
-            let stmt = &program.stmts[pctr];
-            // execute statement if not abstained
-            if self.abstain[pctr] == 0 {
-                // check execution chance
-                if check_chance(stmt.props.chance, &mut self.rand_st) {
-                    // try to eval this statement
-                    let res = match self.eval_stmt(stmt) {
-                        // on error, set the correct line number and bubble up
-                        Err(mut err) => {
-                            err.set_line(stmt.props.onthewayto);
-                            // special treatment for NEXT
-                            if let StmtBody::DoNext(n) = stmt.body {
-                                if let Some(i) = program.labels.get(&n) {
-                                    err.set_line(program.stmts[*i as usize].props.srcline);
-                                }
+                // try to eval this statement
+                let res = match self.eval_stmt(stmt) {
+                    // on error, set the correct line number and bubble up
+                    Err(mut err) => {
+                        err.set_line(stmt.props.onthewayto);
+                        err.set_col(stmt.props.onthewaycol);
+                        err.set_stmt(stmt.props.srcline, stmt.props.stmt_on_line);
+                        // special treatment for NEXT
+                        if let StmtBody::DoNext(n) = stmt.body {
+                            if let Some(target) = program.stmt_by_label(n) {
+                                err.set_line(target.props.srcline);
+                                err.set_col(target.props.srccol);
                             }
+                        }
+                        // in lenient mode, carry on with the next statement if possible
+                        let reading_input = matches!(stmt.body, StmtBody::WriteIn(_));
+                        if self.diagnostics.is_none() || !err.is_recoverable(reading_input) {
                             return Err(err);
                         }
-                        Ok(res)  => res
-                    };
-                    // handle control flow effects
+                        self.report(&err)?;
+                        Flow::Next
+                    }
+                    Ok(res)  => res
+                };
+                if let Some(hooks) = self.stdout.hooks.as_deref_mut() {
                     match res {
-                        Flow::Next    => { }
-                        Flow::Jump(n) => {
-                            self.jumps.push(pctr as u16);  // push the line with the NEXT
-                            pctr = n;
-                            continue;  // do not increment or check for COME FROMs
-                        }
-                        Flow::Back(n) => {
-                            pctr = n;  // will be incremented below after COME FROM check
-                        }
-                        Flow::FromTop => {
-                            pctr = 0;  // start from the beginning, do not push any stack
-                            continue;
-                        }
-                        Flow::End     => break,
+                        Flow::Jump(n) => hooks.on_jump(pctr, n),
+                        Flow::Back(n, _) => hooks.on_jump(pctr, n + 1),
+                        Flow::FromTop => hooks.on_jump(pctr, 0),
+                        Flow::Next | Flow::End => { }
+                    }
+                }
+                // handle control flow effects
+                match res {
+                    Flow::Next    => { }
+                    Flow::Jump(n) => {
+                        self.jumps.push((pctr as u16, true));  // push the line with the NEXT
+                        self.max_jump_depth = self.max_jump_depth.max(self.jumps.len());
+                        // do not increment or check for COME FROMs
+                        return Ok(Step::At(n));
+                    }
+                    Flow::Back(n, true) => {
+                        pctr = n;  // will be incremented below after COME FROM check
                     }
+                    Flow::Back(n, false) => {
+                        // back from NEXT FROM, continue after its origin
+                        return Ok(Step::At(n + 1));
+                    }
+                    Flow::FromTop => {
+                        // start from the beginning, do not push any stack
+                        return Ok(Step::At(0));
+                    }
+                    Flow::End     => return Ok(Step::Done(Termination::GaveUp)),
                 }
             }
-            // if we are on the line with the compiler bug, error out
-            if pctr == self.program.bugline as usize {
-                return IE774.err_with(None, stmt.props.onthewayto);
-            }
-            // try to determine if we have to go to a COME FROM statement
-            // (note: in general, program.stmts[pctr] != stmt)
-            //
-            // the static COME FROM is always a possibility
-            let mut maybe_next = program.stmts[pctr].comefrom;
-            // the complicated case: evaluate all computed-come-from expressions
-            let my_label = program.stmts[pctr].props.label;
-            if program.uses_complex_comefrom && my_label > 0 {
-                for (i, stmt) in program.stmts.iter().enumerate() {
-                    if let StmtBody::ComeFrom(ComeFrom::Expr(ref e)) = stmt.body {
-                        let v = self.eval_expr(e)?.as_u16()?;
-                        if v == my_label {
-                            // as soon as we have multiple candidates, we can bail out
-                            if maybe_next.is_some() {
-                                return IE555.err();
-                            }
-                            maybe_next = Some(i as u16);
+        }
+        // if we are on the line with the compiler bug, error out
+        if pctr == self.program.bugline as usize {
+            return IE774.err_with(None, stmt.props.onthewayto);
+        }
+        // try to determine if we have to go to a COME FROM statement
+        // (note: in general, program.stmts[pctr] != stmt)
+        //
+        // the static COME FROM is always a possibility
+        let mut maybe_next = program.stmts[pctr].comefrom;
+        // the complicated case: evaluate all computed-come-from expressions
+        let my_label = program.stmts[pctr].props.label;
+        if program.uses_complex_comefrom && my_label > 0 {
+            let wayto = stmt.props.onthewayto;
+            for (i, stmt) in program.stmts.iter().enumerate() {
+                if let StmtBody::ComeFrom(ComeFrom::Expr(ref e)) = stmt.body {
+                    // a value that can't be a label is an error (IE275)
+                    let v = self.eval_expr(e).and_then(|v| v.as_u16()).map_err(|mut err| {
+                        err.set_line(wayto);
+                        err
+                    })?;
+                    if v == my_label {
+                        // as soon as we have multiple candidates, we can bail out
+                        if maybe_next.is_some() {
+                            return IE555.err_with(None, wayto);
                         }
+                        maybe_next = Some(i as u16);
                     }
                 }
             }
-            // check for COME FROMs from this line
-            if let Some(next) = maybe_next {
-                let next = next as usize;
-                // check for abstained COME FROM
-                if self.abstain[next] == 0 {
-                    // the COME FROM can also have a % chance
-                    if check_chance(program.stmts[next].props.chance,
-                                    &mut self.rand_st) {
-                        pctr = next;
-                        continue;
+        }
+        // check for COME FROMs from this line
+        if let Some(next) = maybe_next {
+            let next = next as usize;
+            // check for abstained COME FROM
+            if self.abstain[next] == 0 {
+                // the COME FROM can also have a % chance
+                if self.roll_chance(program.stmts[next].props.chance, true,
+                                    stmt.props.onthewayto)? {
+                    // NEXT FROM also behaves like a NEXT on this line
+                    if let StmtBody::NextFrom(_) = program.stmts[next].body {
+                        if self.jumps.len() >= 80 {
+                            return IE123.err_with(Some(&self.jump_chain()),
+                                                  stmt.props.onthewayto);
+                        }
+                        self.jumps.push((pctr as u16, false));
+                        self.max_jump_depth = self.max_jump_depth.max(self.jumps.len());
+                    }
+                    if let Some(hooks) = self.stdout.hooks.as_deref_mut() {
+                        hooks.on_comefrom(pctr, next);
                     }
+                    return Ok(Step::At(next));
                 }
             }
-            // no COME FROM, normal execution
-            pctr += 1;
         }
-        Ok(self.stmt_ctr)
+        // no COME FROM, normal execution
+        Ok(Step::At(pctr + 1))
+    }
+
+    /// Determine if a % chance comes up, for the current statement or (if
+    /// `comefrom` is set) a COME FROM catching it.  When replaying, the outcome
+    /// is taken from the trace; when recording, it is added to the trace.
+    fn roll_chance(&mut self, chance: u8, comefrom: bool, line: usize) -> Res<bool> {
+        if chance == 100 {
+            return Ok(true);
+        }
+        let outcome = match self.replay_step {
+            Some(step) => match if comefrom { step.comefrom } else { step.chance } {
+                Some(outcome) => outcome,
+                None => return IE994.err_with(None, line),
+            },
+            None => check_chance(chance, &mut self.rand_st),
+        };
+        if let Some(step) = self.trace.as_mut().and_then(|trace| trace.last_mut()) {
+            if comefrom {
+                step.comefrom = Some(outcome);
+            } else {
+                step.chance = Some(outcome);
+            }
+        }
+        Ok(outcome)
     }
 
     /// Interpret a single statement.
@@ -259,12 +918,19 @@ impl<'a> Eval<'a> {
             StmtBody::DoNext(n) => {
                 match self.program.labels.get(&n) {
                     // too many jumps on stack already?
-                    Some(_) if self.jumps.len() >= 80 => IE123.err(),
-                    Some(i)                           => Ok(Flow::Jump(*i as usize)),
+                    Some(_) if self.jumps.len() >= 80 => {
+                        IE123.err_with(Some(&self.jump_chain()), 0)
+                    }
+                    Some(i)                           => {
+                        if let Some(ref mut targets) = self.next_targets {
+                            targets.insert(n);
+                        }
+                        Ok(Flow::Jump(*i as usize))
+                    }
                     None                              => IE129.err(),
                 }
             }
-            StmtBody::ComeFrom(_) => {
+            StmtBody::ComeFrom(_) | StmtBody::NextFrom(_) => {
                 // nothing to do here at runtime
                 Ok(Flow::Next)
             }
@@ -272,9 +938,9 @@ impl<'a> Eval<'a> {
                 let n = self.eval_expr(expr)?.as_u32();
                 // this expect() is safe: if the third arg is true, there will
                 // be no Ok(None) returns
-                let next = pop_jumps(&mut self.jumps, n, true, 0)?
+                let (next, check_comefrom) = pop_jumps(&mut self.jumps, n, true, 0)?
                     .expect("https://xkcd.com/378/ ?!");
-                Ok(Flow::Back(next as usize))
+                Ok(Flow::Back(next as usize, check_comefrom))
             }
             StmtBody::Forget(ref expr) => {
                 let n = self.eval_expr(expr)?.as_u32();
@@ -323,34 +989,57 @@ impl<'a> Eval<'a> {
                 }
                 Ok(Flow::Next)
             }
-            StmtBody::ReadOut(ref vars) => {
-                for var in vars {
-                    match *var {
+            StmtBody::ReadOut(ref exprs) => {
+                // in batch mode, collect the numbers to write them in one row
+                let batch = self.roman_sep == RomanSep::Batch;
+                let mut vals = vec![];
+                for expr in exprs {
+                    match *expr {
                         // read out whole array
                         Expr::Var(ref var) if var.is_dim() => {
+                            if !vals.is_empty() {
+                                write_roman_row(&mut self.stdout, &vals, self.roman_width, 0)?;
+                                vals.clear();
+                            }
                             self.array_readout(var)?;
                         }
-                        // read out single var or array element
-                        Expr::Var(ref var) => {
-                            let varval = self.lookup(var)?;
-                            write_number(self.stdout, varval.as_u32(), 0)?;
+                        // read out anything else: single var, array element,
+                        // constant or expression
+                        _ => {
+                            let val = self.eval_expr(expr)?.as_u32();
+                            if let Some(ref mut log) = self.output_log {
+                                log.push(OutputItem::Number(val));
+                            }
+                            if batch {
+                                vals.push(val);
+                            } else {
+                                self.write_number(val)?;
+                            }
                         }
-                        // read out constant
-                        Expr::Num(_, v) => write_number(self.stdout, v, 0)?,
-                        // others will not be generated
-                        _ => return IE994.err(),
                     };
                 }
+                if !vals.is_empty() {
+                    write_roman_row(&mut self.stdout, &vals, self.roman_width, 0)?;
+                }
                 Ok(Flow::Next)
             }
             StmtBody::WriteIn(ref vars) => {
+                // numbers put side by side may be a prompt for the input
+                self.flush_roman_row()?;
                 for var in vars {
                     if var.is_dim() {
                         // write in whole array
                         self.array_writein(var)?;
                     } else {
                         // write in single var or array element
-                        let n = read_number(0)?;
+                        let n = match self.input_mode {
+                            InputMode::Number => read_number(self.stdin, 0)?,
+                            InputMode::Digits => read_number_or_digits(self.stdin, 0)?,
+                            InputMode::Radix => read_number_radix(self.stdin, 0)?,
+                            InputMode::Saturating => read_number_saturating(self.stdin, 0)?,
+                            InputMode::Byte => read_byte(self.stdin) as u32,
+                            InputMode::Roman => read_roman(self.stdin, 0)?,
+                        };
                         self.assign(var, Val::from_u32(n))?;
                     }
                 }
@@ -358,9 +1047,13 @@ impl<'a> Eval<'a> {
             }
             // this one is only generated by the constant-program optimizer
             StmtBody::Print(ref s) => {
+                self.flush_roman_row()?;
                 if self.stdout.write_all(s).is_err() {
                     return IE252.err();
                 }
+                if let Some(ref mut log) = self.output_log {
+                    log.extend(s.iter().map(|&b| OutputItem::Byte(b)));
+                }
                 Ok(Flow::Next)
             }
             StmtBody::TryAgain => Ok(Flow::FromTop),
@@ -369,6 +1062,14 @@ impl<'a> Eval<'a> {
         }
     }
 
+    /// Evaluate an expression against the current state of the variables, for
+    /// interactive shells.  Variables are numbered as in the program's
+    /// statements, not as in the source.  Nothing is changed.
+    #[allow(dead_code)]  // for interactive shells
+    pub fn eval_expression(&self, expr: &Expr) -> Res<Val> {
+        self.eval_expr(expr)
+    }
+
     /// Evaluate an expression to a value.
     fn eval_expr(&self, expr: &Expr) -> Res<Val> {
         match *expr {
@@ -378,17 +1079,19 @@ impl<'a> Eval<'a> {
             },
             Expr::Var(ref var) => self.lookup(var),
             Expr::Mingle(ref vx, ref wx) => {
-                let v = self.eval_expr(vx)?.as_u32();
-                let w = self.eval_expr(wx)?.as_u32();
-                let v = check_ovf(v, 0)?;
-                let w = check_ovf(w, 0)?;
-                Ok(Val::I32(mingle(v, w)))
+                let mut v = self.eval_expr(vx)?.as_u32();
+                let mut w = self.eval_expr(wx)?.as_u32();
+                if self.truncate_mingle {
+                    v &= 0xFFFF;
+                    w &= 0xFFFF;
+                }
+                Ok(Val::I32(mingle_with(v, w, self.mingle_order, 0)?))
             }
             Expr::Select(vtype, ref vx, ref wx) => {
                 let v = self.eval_expr(vx)?;
                 let w = self.eval_expr(wx)?;
                 if vtype == VType::I16 {
-                    Ok(Val::I16(select(v.as_u32(), w.as_u16()? as u32) as u16))
+                    Ok(Val::I16(select(v.as_u32(), self.operand_u16(w, wx)? as u32) as u16))
                 } else {
                     Ok(Val::I32(select(v.as_u32(), w.as_u32())))
                 }
@@ -396,21 +1099,21 @@ impl<'a> Eval<'a> {
             Expr::And(vtype, ref vx) => {
                 let v = self.eval_expr(vx)?;
                 match vtype {
-                    VType::I16 => Ok(Val::I16(and_16(v.as_u16()? as u32) as u16)),
+                    VType::I16 => Ok(Val::I16(and_16(self.operand_u16(v, vx)? as u32) as u16)),
                     VType::I32 => Ok(Val::I32(and_32(v.as_u32()))),
                 }
             }
             Expr::Or(vtype, ref vx) => {
                 let v = self.eval_expr(vx)?;
                 match vtype {
-                    VType::I16 => Ok(Val::I16(or_16(v.as_u16()? as u32) as u16)),
+                    VType::I16 => Ok(Val::I16(or_16(self.operand_u16(v, vx)? as u32) as u16)),
                     VType::I32 => Ok(Val::I32(or_32(v.as_u32()))),
                 }
             }
             Expr::Xor(vtype, ref vx) => {
                 let v = self.eval_expr(vx)?;
                 match vtype {
-                    VType::I16 => Ok(Val::I16(xor_16(v.as_u16()? as u32) as u16)),
+                    VType::I16 => Ok(Val::I16(xor_16(self.operand_u16(v, vx)? as u32) as u16)),
                     VType::I32 => Ok(Val::I32(xor_32(v.as_u32()))),
                 }
             }
@@ -436,12 +1139,12 @@ impl<'a> Eval<'a> {
             Expr::RsRshift(ref vx, ref wx) => {
                 let v = self.eval_expr(vx)?;
                 let w = self.eval_expr(wx)?;
-                Ok(Val::I32(v.as_u32() >> w.as_u32()))
+                Ok(Val::I32(v.as_u32().checked_shr(w.as_u32()).unwrap_or(0)))
             }
             Expr::RsLshift(ref vx, ref wx) => {
                 let v = self.eval_expr(vx)?;
                 let w = self.eval_expr(wx)?;
-                Ok(Val::I32(v.as_u32() << w.as_u32()))
+                Ok(Val::I32(v.as_u32().checked_shl(w.as_u32()).unwrap_or(0)))
             }
             // Expr::RsEqual(ref vx, ref wx) => {
             //     let v = self.eval_expr(vx)?;
@@ -474,27 +1177,73 @@ impl<'a> Eval<'a> {
     /// Dimension an array.
     fn array_dim(&mut self, var: &Var, dims: &[Expr]) -> Res<()> {
         let dims = self.eval_subs(dims)?;
+        let (preserve, max) = (self.preserve_on_redim, self.max_elems);
         match *var {
-            Var::A16(n, _) => self.tail[n].dimension(dims, 0),
-            Var::A32(n, _) => self.hybrid[n].dimension(dims, 0),
+            Var::A16(n, _) => self.tail[n].dimension_capped(dims, preserve, max, 0),
+            Var::A32(n, _) => self.hybrid[n].dimension_capped(dims, preserve, max, 0),
             _ => IE994.err()
         }
     }
 
     /// Assign to a variable.
     fn assign(&mut self, var: &Var, val: Val) -> Res<()> {
+        if !self.read_only.is_empty() && self.read_only.contains(&var.unique()) {
+            let name = self.program.source_var(var).to_string();
+            return IE212.err_with(Some(&name), 0);
+        }
         match *var {
-            Var::I16(n) => Ok(self.spot[n].assign(val.as_u16()?)),
-            Var::I32(n) => Ok(self.twospot[n].assign(val.as_u32())),
+            Var::I16(n) => {
+                let val = self.var_u16(var, val)?;
+                self.spot[n].assign(val);
+                self.assigned(var, &[], Val::I16(val));
+            }
+            Var::I32(n) => {
+                self.twospot[n].assign(val.as_u32());
+                self.assigned(var, &[], Val::I32(val.as_u32()));
+            }
             Var::A16(n, ref subs) => {
                 let subs = self.eval_subs(subs)?;
-                self.tail[n].set_md(&subs, val.as_u16()?, 0)
+                let val = self.var_u16(var, val)?;
+                self.tail[n].set_md(&subs, val, 0)?;
+                self.assigned(var, &subs, Val::I16(val));
             }
             Var::A32(n, ref subs) => {
                 let subs = self.eval_subs(subs)?;
-                self.hybrid[n].set_md(&subs, val.as_u32(), 0)
+                self.hybrid[n].set_md(&subs, val.as_u32(), 0)?;
+                self.assigned(var, &subs, Val::I32(val.as_u32()));
             }
         }
+        Ok(())
+    }
+
+    /// Tell the hooks, if any, about an assignment.
+    fn assigned(&mut self, var: &Var, subs: &[usize], val: Val) {
+        if self.stdout.hooks.is_none() {
+            return;
+        }
+        let mut var = self.program.source_var(var);
+        if let Var::A16(_, ref mut exprs) | Var::A32(_, ref mut exprs) = var {
+            *exprs = subs.iter().map(|&i| Expr::Num(VType::I32, i as u32)).collect();
+        }
+        if let Some(hooks) = self.stdout.hooks.as_deref_mut() {
+            hooks.on_assign(&var, &val);
+        }
+    }
+
+    /// Cast a value to be assigned to a 16-bit variable, naming the variable if
+    /// it doesn't fit.
+    fn var_u16(&self, var: &Var, val: Val) -> Res<u16> {
+        val.as_u16().or_else(|_| {
+            IE275.err_with(Some(&self.program.source_var(var).to_string()), 0)
+        })
+    }
+
+    /// Cast the value of a 16-bit operator's operand, naming the operand if it
+    /// doesn't fit.
+    fn operand_u16(&self, val: Val, expr: &Expr) -> Res<u16> {
+        val.as_u16().or_else(|_| {
+            IE275.err_with(Some(&self.program.source_expr(expr).to_string()), 0)
+        })
     }
 
     /// Look up the value of a variable.
@@ -534,6 +1283,9 @@ impl<'a> Eval<'a> {
     }
 
     /// Process an IGNORE or REMEMBER statement.  Cannot fail.
+    ///
+    /// Arrays are always IGNOREd as a whole (the parser does not allow subscripts
+    /// here); the element setters in `Bind` check the flag of the whole array.
     fn set_rw(&mut self, var: &Var, rw: bool) {
         match *var {
             Var::I16(n) => self.spot[n].rw = rw,
@@ -546,7 +1298,11 @@ impl<'a> Eval<'a> {
     /// P()rocess an ABSTAIN or REINSTATE statement.  Cannot fail.
     fn abstain(&mut self, what: &ast::Abstain, f: &dyn Fn(u32) -> u32) {
         if let ast::Abstain::Label(lbl) = *what {
-            let idx = self.program.labels[&lbl] as usize;
+            // a label that does not exist has nothing to abstain from
+            let idx = match self.program.labels.get(&lbl) {
+                Some(&idx) => idx as usize,
+                None => return,
+            };
             if self.program.stmts[idx].body != StmtBody::GiveUp {
                 self.abstain[idx] = f(self.abstain[idx]);
             }
@@ -556,29 +1312,144 @@ impl<'a> Eval<'a> {
                     self.abstain[i] = f(self.abstain[i]);
                 }
             }
+            match self.gerund_abstain.iter_mut().find(|(gerund, _)| gerund == what) {
+                Some((_, states)) => {
+                    states[0] = f(states[0]);
+                    states[1] = f(states[1]);
+                }
+                None => self.gerund_abstain.push((what.clone(), [f(0), f(1)])),
+            }
+        }
+    }
+
+    /// Output a number, honoring the selected separation.
+    fn write_number(&mut self, val: u32) -> Res<()> {
+        if self.roman_sep == RomanSep::Newline || self.roman_sep == RomanSep::Batch {
+            if self.roman_width == 0 {
+                if val as usize >= ROMAN_CACHE_SIZE {
+                    return write_number(&mut self.stdout, val, 0);
+                }
+                let idx = val as usize;
+                if self.roman_cache.len() <= idx {
+                    self.roman_cache.resize(idx + 1, String::new());
+                }
+                if self.roman_cache[idx].is_empty() {
+                    self.roman_cache[idx] = to_roman(val);
+                }
+                return write_bytes(&mut self.stdout, self.roman_cache[idx].as_bytes(), 0);
+            }
+            return write_roman_row(&mut self.stdout, &[val], self.roman_width, 0);
+        }
+        let sep = if self.roman_sep == RomanSep::Space { " " } else { "" };
+        let row = self.roman_row.get_or_insert_with(|| (String::new(), String::new()));
+        if !row.0.is_empty() || !row.1.is_empty() {
+            row.0.push_str(sep);
+            row.1.push_str(sep);
+        }
+        push_roman(&mut row.0, &mut row.1, val, self.roman_width);
+        Ok(())
+    }
+
+    /// Write out the numbers collected side by side, if any.
+    fn flush_roman_row(&mut self) -> Res<()> {
+        match self.roman_row.take() {
+            Some((bars, digits)) => {
+                let row = format!("{}\n{}\n", bars, digits);
+                write_bytes(&mut self.stdout, row.as_bytes(), 0)
+            }
+            None => Ok(()),
         }
     }
 
+    /// Describe the NEXT stack for diagnostics: the source lines of the
+    /// statements that pushed the entries, with their labels if they have one.
+    fn jump_chain(&self) -> String {
+        let entries = self.jumps.iter().map(|&(pctr, _)| {
+            let props = &self.program.stmts[pctr as usize].props;
+            if props.label > 0 {
+                format!("{} ({})", props.srcline, props.label)
+            } else {
+                props.srcline.to_string()
+            }
+        }).collect::<Vec<_>>();
+        format!("NEXTED FROM LINES {}", entries.join(", "))
+    }
+
+    /// Report a recoverable error in lenient mode.
+    fn report(&mut self, err: &RtError) -> Res<()> {
+        // numbers put side by side should come before the message
+        self.flush_roman_row()?;
+        self.recovered_ctr += 1;
+        if let Some(ref mut sink) = self.diagnostics {
+            if sink.write_all(err.to_string().as_bytes()).is_err() {
+                return IE252.err();
+            }
+        }
+        Ok(())
+    }
+
     /// Array readout helper.
     fn array_readout(&mut self, var: &Var) -> Res<()> {
+        self.flush_roman_row()?;
+        let before = self.last_out;
         let state = &mut self.last_out;
         match *var {
-            Var::A16(n, _) => self.tail[n].readout(self.stdout, state, 0),
-            Var::A32(n, _) => self.hybrid[n].readout(self.stdout, state, 0),
+            Var::A16(n, _) => self.tail[n].readout(&mut self.stdout, state, 0),
+            Var::A32(n, _) => self.hybrid[n].readout(&mut self.stdout, state, 0),
             _ => IE994.err()
+        }?;
+        if self.debug || self.output_log.is_some() {
+            // replay the tape steps to show how the output came about
+            let vals: Vec<u16> = match *var {
+                Var::A16(n, _) => self.tail[n].val.iter().collect(),
+                Var::A32(n, _) => self.hybrid[n].val.iter().map(|v| v as u16).collect(),
+                _ => vec![],
+            };
+            let mut state = before;
+            for val in vals {
+                let old = state;
+                let byte = tape_out(&mut state, val);
+                if self.debug {
+                    println!("tape out: {} -> byte {} (state {} -> {})", val, byte, old, state);
+                }
+                if let Some(ref mut log) = self.output_log {
+                    log.push(OutputItem::Byte(byte));
+                }
+            }
         }
+        Ok(())
     }
 
     /// Array writein helper.
     fn array_writein(&mut self, var: &Var) -> Res<()> {
         let state = &mut self.last_in;
         match *var {
-            Var::A16(n, _) => self.tail[n].writein(state, 0),
-            Var::A32(n, _) => self.hybrid[n].writein(state, 0),
+            Var::A16(n, _) => self.tail[n].writein(self.stdin, state, 0),
+            Var::A32(n, _) => self.hybrid[n].writein(self.stdin, state, 0),
             _ => IE994.err()
         }
     }
 
+    /// Get the Turing tape states of binary input and output, i.e. the last
+    /// byte read and written (bit-reversed for output).
+    #[allow(dead_code)]  // for inspecting state after a run
+    pub fn tape_state(&self) -> (u8, u8) {
+        (self.last_in, self.last_out)
+    }
+
+    /// Get the current contents of an array variable, in storage order: the
+    /// first subscript varies fastest (column-major order).
+    /// The array is given with its number from the source, like for `preset`.
+    /// Returns None if the variable is not an array or not used by the program.
+    #[allow(dead_code)]  // for inspecting state after a run
+    pub fn dump_array(&self, var: &Var) -> Option<Vec<Val>> {
+        match self.program_var(var)? {
+            Var::A16(n, _) => Some(self.tail.get(n)?.val.iter().map(Val::I16).collect()),
+            Var::A32(n, _) => Some(self.hybrid.get(n)?.val.iter().map(Val::I32).collect()),
+            _ => None,
+        }
+    }
+
     /// Debug helpers.
     fn dump_state(&self) {
         self.dump_state_one(&self.spot, ".");
@@ -600,3 +1471,111 @@ impl<'a> Eval<'a> {
         }
     }
 }
+
+
+/// Wraps the output stream to count the bytes and lines written, and refuse
+/// writes beyond a limit.  It also owns the evaluator's hooks, so that they see all
+/// output.
+struct CountingOutput<'a> {
+    inner: &'a mut (dyn Write + Send),
+    count: usize,
+    limit: usize,
+    lines: usize,
+    line_limit: usize,
+    hooks: Option<&'a mut (dyn EvalHooks + Send)>,
+}
+
+impl Write for CountingOutput<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.limit - self.count {
+            return Err(io::Error::other("output limit exceeded"));
+        }
+        let newlines = buf.iter().filter(|&&b| b == b'\n').count();
+        if newlines > self.line_limit - self.lines {
+            return Err(io::Error::other("output line limit exceeded"));
+        }
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        self.lines += buf[..n].iter().filter(|&&b| b == b'\n').count();
+        if let Some(hooks) = self.hooks.as_deref_mut() {
+            hooks.on_output(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+/// An input stream serving queued chunks of text, for feeding the evaluator
+/// exact input in tests.
+///
+/// `read_line` returns exactly one queued chunk, so that each WRITE IN of a
+/// number gets the line meant for it; byte reads go through the chunks in order.
+/// When the queue is exhausted, the stream is at EOF.
+#[allow(dead_code)]  // for driving the evaluator from tests
+#[derive(Default)]
+pub struct ScriptedInput {
+    queue: VecDeque<String>,
+    /// Bytes of the first chunk already consumed.
+    pos: usize,
+}
+
+#[allow(dead_code)]
+impl ScriptedInput {
+    pub fn new() -> ScriptedInput {
+        Default::default()
+    }
+
+    /// Queue a line of input; a newline is added.
+    pub fn push_line(&mut self, line: &str) {
+        self.queue.push_back(format!("{}\n", line));
+    }
+
+    /// Queue some raw input.
+    pub fn push_bytes(&mut self, bytes: &str) {
+        self.queue.push_back(bytes.into());
+    }
+}
+
+impl Read for ScriptedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let avail = self.fill_buf()?;
+            let n = avail.len().min(buf.len());
+            buf[..n].copy_from_slice(&avail[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for ScriptedInput {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.queue.front().is_some_and(|s| self.pos == s.len()) {
+            self.queue.pop_front();
+            self.pos = 0;
+        }
+        Ok(self.queue.front().map_or(&[], |s| &s.as_bytes()[self.pos..]))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        self.fill_buf()?;
+        match self.queue.pop_front() {
+            Some(s) => {
+                let rest = String::from_utf8_lossy(&s.as_bytes()[self.pos..]);
+                buf.push_str(&rest);
+                self.pos = 0;
+                Ok(rest.len())
+            }
+            None => Ok(0),
+        }
+    }
+}
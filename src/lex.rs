@@ -34,6 +34,7 @@ struct PestLexer;
 
 pub struct SrcToken {
     pub line: SrcLine,
+    pub col: usize,  // 1-based, of the token start
     pub rule: Rule,
     pub value: u32  // for NUMBER tokens
 }
@@ -43,6 +44,13 @@ pub struct Lexer<'a> {
     startline: SrcLine,
     stash:     Vec<SrcToken>,
     lastline:  SrcLine,
+    lastcol:   usize,
+    // Pest's line_col() scans from the start of the input every time, so
+    // the position is tracked here; tokens come in order
+    input:     &'a str,
+    offset:    usize,  // how far the input has been scanned
+    line:      usize,  // 0-based line at the offset
+    linestart: usize,  // offset of that line's start
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -52,6 +60,7 @@ impl<'a> Iterator for Lexer<'a> {
         let ret = self.inner_next();
         if let Some(tok) = ret {
             self.lastline = tok.line;
+            self.lastcol = tok.col;
             return Some(tok);
         }
         None
@@ -65,21 +74,40 @@ impl<'a> Lexer<'a> {
             self.inner.next().map(|pair| {
                 let rule = pair.as_rule();
                 let text = pair.as_str();
-                let line = pair.as_span().end_pos().line_col().0 - 1 + self.startline;
+                let col = self.line_col(pair.as_span().start()).1;
+                let line = self.line_col(pair.as_span().end()).0 + self.startline;
                 // convert into SrcToken
                 if rule == Rule::NUMBER {
-                    SrcToken { line, rule, value: text.trim().parse().unwrap_or(u32::max_value()) }
+                    let value = text.trim().parse().unwrap_or(u32::max_value());
+                    SrcToken { line, col, rule, value }
                 } else if rule == Rule::WOW {
                     // handle ! = '. combination
-                    self.stash.push(SrcToken { line, rule: Rule::SPOT, value: 0 });
-                    SrcToken { line, rule: Rule::SPARK, value: 0 }
+                    self.stash.push(SrcToken { line, col: col + 1, rule: Rule::SPOT, value: 0 });
+                    SrcToken { line, col, rule: Rule::SPARK, value: 0 }
                 } else {
-                    SrcToken { line, rule, value: 0 }
+                    SrcToken { line, col, rule, value: 0 }
                 }
             })
         })
     }
 
+    /// Get the 0-based line and 1-based column (in characters) of an offset.
+    fn line_col(&mut self, offset: usize) -> (usize, usize) {
+        if offset < self.offset {
+            self.offset = 0;
+            self.line = 0;
+            self.linestart = 0;
+        }
+        for (i, b) in self.input[self.offset..offset].bytes().enumerate() {
+            if b == b'\n' {
+                self.line += 1;
+                self.linestart = self.offset + i + 1;
+            }
+        }
+        self.offset = offset;
+        (self.line, self.input[self.linestart..offset].chars().count() + 1)
+    }
+
     pub fn peek(&mut self) -> Option<Rule> {
         if !self.stash.is_empty() {
             return self.stash.last().map(|v| v.rule);
@@ -100,10 +128,15 @@ impl<'a> Lexer<'a> {
     pub fn lineno(&self) -> SrcLine {
         self.lastline
     }
+
+    pub fn colno(&self) -> usize {
+        self.lastcol
+    }
 }
 
 pub fn lex(s: &str, startline: usize) -> Lexer {
-    // always succeeds since we have an UNKNOWN token
+    // always succeeds since we have an UNKNOWN token, and no tokens are fine too
     let inner = PestLexer::parse(Rule::tokens, s).unwrap();
-    Lexer { inner, startline, stash: vec![], lastline: startline }
+    Lexer { inner, startline, stash: vec![], lastline: startline, lastcol: 0,
+            input: s, offset: 0, line: 0, linestart: 0 }
 }
@@ -16,6 +16,7 @@
 // -------------------------------------------------------------------------------------------------
 
 #![feature(box_patterns)]
+#![cfg_attr(test, feature(test))]
 #![recursion_limit="500"]
 
 /// Main program for Rick.
@@ -32,9 +33,14 @@ mod codegen;
 mod stdops;
 mod syslib;
 mod mandel;
+mod pipeline;
+#[cfg(test)]
+mod bench;
+#[cfg(test)]
+mod tests;
 
 use std::env::args;
-use std::io::{Read, Write, stdout, stderr};
+use std::io::{BufReader, Read, Write, stdin, stdout, stderr};
 use std::fs::{File, remove_file};
 use std::process::{Command, Stdio, exit};
 use std::sync::mpsc;
@@ -43,8 +49,9 @@ use std::time::{Duration, Instant};
 
 use parse::Parser;
 use opt::Optimizer;
-use eval::Eval;
+use eval::{Eval, InputMode, RomanSep};
 use codegen::Generator;
+use stdops::MingleOrder;
 
 
 fn main() {
@@ -53,7 +60,7 @@ fn main() {
         Err(err) => {
             let mut stderr = stderr();
             write!(stderr, "{}", err.to_string()).unwrap();
-            exit(1);
+            exit(err.exit_code());
         }
     }
 }
@@ -68,8 +75,38 @@ fn main_inner() -> Result<i32, err::RtError> {
     opts.optflag("O", "rustc-opt", "run rustc in optimized mode");
     opts.optflag("R", "no-random", "use deterministic random seed");
     opts.optflag("F", "no-constout", "do not optimize away const-output programs");
+    opts.optopt("", "opt-budget", "stop optimizing after about MS milliseconds", "MS");
+    opts.optopt("", "specialize", "optimize for the fixed input read from FILE (with -o, not \
+                                   with -F)", "FILE");
     opts.optflag("d", "debug", "activate printing out debug messages");
     opts.optflag("t", "timing", "print out timing messages");
+    opts.optflag("w", "warnings", "print warnings about suspicious code");
+    opts.optopt("", "roman-sep", "separate numbers on output by newline (default), space, \
+                                  none, or batch those of one READ OUT (interpreter only)",
+                "SEP");
+    opts.optopt("", "roman-width", "pad numbers on output to N columns (interpreter only)", "N");
+    opts.optopt("", "scalar-input", "read scalars in WRITE IN as numbers (default), digits, \
+                                     radix, saturating, bytes or roman (interpreter only)", "MODE");
+    opts.optflag("", "preserve-redim", "keep array contents when redimensioning with as many \
+                                        dimensions (interpreter only)");
+    opts.optflag("", "truncate-mingle", "cut mingle operands to 16 bits instead of failing \
+                                         (interpreter only)");
+    opts.optflag("", "low-mingle", "mingle the first operand into the lower bit of each pair \
+                                    (interpreter only, not with -o)");
+    opts.optflag("", "fall-off-ok", "end the program at its end even without GIVE UP \
+                                     (interpreter only)");
+    opts.optopt("", "max-elems", "refuse to dimension arrays with more elements (interpreter \
+                                  only)", "N");
+    opts.optopt("", "max-output", "stop with an error after N bytes of output (interpreter \
+                                   only)", "N");
+    opts.optopt("", "max-stmts", "stop the program, without error, after N statements \
+                                  (interpreter only)", "N");
+    opts.optopt("", "max-output-lines", "stop with an error after N lines of output \
+                                         (interpreter only)", "N");
+    opts.optflag("", "lenient", "report bad input and go on with the next statement \
+                                 (interpreter only)");
+    opts.optflag("v", "verbose", "print statistics when the program gives up (interpreter \
+                                 only)");
     opts.optflag("h", "help", "print help message");
 
     // parse args
@@ -88,12 +125,77 @@ fn main_inner() -> Result<i32, err::RtError> {
     let compile_flag = !matches.opt_present("i");
     let debug_flag = matches.opt_present("d");
     let timing_flag = matches.opt_present("t");
+    let warn_flag = matches.opt_present("w");
     let opt_flag = matches.opt_present("o");
     let bug_flag = !matches.opt_present("b");
     let rand_flag = !matches.opt_present("R");
     let rustc_flag = !matches.opt_present("c");
     let rustc_opt_flag = matches.opt_present("O");
     let const_out_flag = !matches.opt_present("F");
+    // specializing reduces the program to constant output, which -F forbids
+    if !const_out_flag && matches.opt_present("specialize") {
+        return err::IE990.err();
+    }
+    let preserve_flag = matches.opt_present("preserve-redim");
+    let truncate_flag = matches.opt_present("truncate-mingle");
+    // the optimizer folds and rewrites mingles in the default order
+    let mingle_order = if matches.opt_present("low-mingle") {
+        if opt_flag {
+            return err::IE990.err();
+        }
+        MingleOrder::FirstLow
+    } else {
+        MingleOrder::FirstHigh
+    };
+    let fall_off_flag = matches.opt_present("fall-off-ok");
+    let lenient_flag = matches.opt_present("lenient");
+    let verbose_flag = matches.opt_present("v");
+    let opt_budget = match matches.opt_str("opt-budget").map(|s| s.parse()) {
+        None          => None,
+        Some(Ok(ms))  => Some(Duration::from_millis(ms)),
+        Some(Err(_))  => return err::IE990.err(),
+    };
+    let max_elems = match matches.opt_str("max-elems").map(|s| s.parse()) {
+        None         => None,
+        Some(Ok(n))  => Some(n),
+        Some(Err(_)) => return err::IE990.err(),
+    };
+    let max_output = match matches.opt_str("max-output").map(|s| s.parse()) {
+        None         => None,
+        Some(Ok(n))  => Some(n),
+        Some(Err(_)) => return err::IE990.err(),
+    };
+    let max_output_lines = match matches.opt_str("max-output-lines").map(|s| s.parse()) {
+        None         => None,
+        Some(Ok(n))  => Some(n),
+        Some(Err(_)) => return err::IE990.err(),
+    };
+    let max_stmts = match matches.opt_str("max-stmts").map(|s| s.parse()) {
+        None         => None,
+        Some(Ok(n))  => Some(n),
+        Some(Err(_)) => return err::IE990.err(),
+    };
+    let roman_sep = match matches.opt_str("roman-sep").as_ref().map(|s| &s[..]) {
+        None | Some("newline") => RomanSep::Newline,
+        Some("space") => RomanSep::Space,
+        Some("none")  => RomanSep::None,
+        Some("batch") => RomanSep::Batch,
+        Some(_)       => return err::IE990.err(),
+    };
+    let roman_width = match matches.opt_str("roman-width").map(|s| s.parse()) {
+        None         => 0,
+        Some(Ok(n))  => n,
+        Some(Err(_)) => return err::IE990.err(),
+    };
+    let input_mode = match matches.opt_str("scalar-input").as_ref().map(|s| &s[..]) {
+        None | Some("numbers") => InputMode::Number,
+        Some("digits")     => InputMode::Digits,
+        Some("radix")      => InputMode::Radix,
+        Some("saturating") => InputMode::Saturating,
+        Some("bytes")      => InputMode::Byte,
+        Some("roman")      => InputMode::Roman,
+        Some(_)            => return err::IE990.err(),
+    };
 
     // no input file? -> do nothing
     if matches.free.is_empty() {
@@ -130,10 +232,42 @@ fn main_inner() -> Result<i32, err::RtError> {
         Err(err)    => return Err(err),
     };
 
+    // check for suspicious code if wanted
+    if warn_flag {
+        for (var, srcline) in Optimizer::check_stash_balance(&program) {
+            eprintln!("warning: {} is RETRIEVEd on line {}, but never STASHed",
+                      program.source_var(&var), srcline);
+        }
+        for (lbl, srcline) in Optimizer::check_abstain_labels(&program) {
+            eprintln!("warning: label ({}) is ABSTAINed from or REINSTATEd on line {}, \
+                       but does not exist", lbl, srcline);
+        }
+    }
+
     // optimize if wanted
     let t1 = Instant::now();
     if opt_flag {
-        program = Optimizer::new(program, const_out_flag).optimize();
+        let mut optimizer = Optimizer::new(program, const_out_flag);
+        if let Some(budget) = opt_budget {
+            optimizer.set_budget(budget);
+        }
+        if let Some(ref name) = matches.opt_str("specialize") {
+            let mut input = Vec::new();
+            if File::open(name).and_then(|mut f| f.read_to_end(&mut input)).is_err() {
+                return err::IE777.err();
+            }
+            optimizer.set_fixed_input(input);
+        }
+        let (opt_program, stats) = optimizer.optimize();
+        program = opt_program;
+        if warn_flag && stats.passes_skipped > 0 {
+            eprintln!("warning: optimizer budget exceeded after {:?}, skipped {} passes",
+                      stats.budget_used, stats.passes_skipped);
+        }
+        if warn_flag && stats.const_out_skipped {
+            eprintln!("warning: the program's output looks constant, but the constant \
+                       output optimization is disabled by -F");
+        }
         if debug_flag {
             println!("Optimized program:\n{}", program);
         }
@@ -165,15 +299,54 @@ fn main_inner() -> Result<i32, err::RtError> {
             println!("rustc:      {:?}", (t4 - t3));
         }
     } else {
+        let mut stdin = BufReader::new(stdin());
         let mut stdout = stdout();
+        let mut summary_out = stderr();
+        let mut stderr = stderr();
         if debug_flag {
             println!("Running:");
         }
-        let mut eval = Eval::new(&program, &mut stdout, debug_flag, rand_flag);
-        let num = eval.eval()?;
+        let mut eval = Eval::new(&program, &mut stdin, &mut stdout, debug_flag, rand_flag);
+        eval.set_roman_sep(roman_sep);
+        eval.set_roman_width(roman_width);
+        eval.set_input_mode(input_mode);
+        eval.set_preserve_on_redim(preserve_flag);
+        eval.set_truncate_mingle(truncate_flag);
+        eval.set_mingle_order(mingle_order);
+        eval.set_fall_off_ok(fall_off_flag);
+        if let Some(max) = max_elems {
+            eval.set_max_elems(max);
+        }
+        if let Some(max) = max_output {
+            eval.set_max_output(max);
+        }
+        if let Some(max) = max_output_lines {
+            eval.set_max_output_lines(max);
+        }
+        if let Some(max) = max_stmts {
+            eval.set_max_stmts(max);
+        }
+        if lenient_flag {
+            eval.set_lenient(&mut stderr);
+        }
+        if verbose_flag {
+            eval.set_summary(&mut summary_out);
+        }
+        let outcome = match eval.eval() {
+            Ok(outcome) => outcome,
+            // in debug mode, point to the location in the source
+            Err(err) if debug_flag => {
+                eprint!("{}", err.display_with_source(&code));
+                return Ok(err.exit_code());
+            }
+            Err(err) => return Err(err),
+        };
         let t3 = Instant::now();
         if timing_flag {
-            println!("#stmts:     {:?}", num);
+            println!("#stmts:     {:?}", outcome.stmts);
+            println!("ended:      {:?}", outcome.termination);
+            println!("#executed:  {:?}", eval.executed_count());
+            println!("NEXT depth: {:?}", eval.max_jump_depth());
             println!("parsing:    {:?}", (t1 - t0));
             println!("optimizing: {:?}", (t2 - t1));
             println!("execute:    {:?}", (t3 - t2));
@@ -19,9 +19,14 @@
 ///
 /// The optimizer gets the whole program and does several passes.
 ///
-/// * constant folding: just reduces (sub)expressions involving no variables
+/// * floatlib stripping: removes the floatlib if the parser added it for a NEXT
+///   that can never be executed
+/// * constant folding: just reduces (sub)expressions involving no variables,
+///   also in array subscripts, which includes the idioms for building 32-bit
+///   constants from 16-bit halves (mingles, and selects of mingled constants)
 /// * expressions: looks for common patterns of INTERCAL operator expressions
 ///   and replaces them by equivalent expressions involving native Rust operators
+///   (a rewrite is only kept if it makes the expression cheaper, see `expr_cost`)
 /// * constant output (can be disabled): if the program neither uses random numbers
 ///   nor takes any input, its output must be constant - the optimizer generates
 ///   this output using the Eval interpreter and replaces the program by a single
@@ -38,339 +43,249 @@
 /// route of C-INTERCAL and use a DSL for generic pattern matching.
 
 use std::collections::BTreeMap;
-use std::io::Cursor;
+use std::time::{Duration, Instant};
 use std::u16;
 
-use crate::ast::{Program, Stmt, StmtBody, Expr, Var, VarInfo, VType, Abstain};
+use crate::ast::{Program, Stmt, StmtBody, Expr, Var, VarInfo, VType, Abstain, Label,
+                 ExprVisitor, StmtVisitor};
+use crate::err::IE129;
 use crate::eval;
+use crate::lex::SrcLine;
 use crate::stdops::{mingle, select, and_16, and_32, or_16, or_32, xor_16, xor_32};
 
 
+/// A named optimizer pass.
+pub type Pass = (&'static str, fn(Program) -> Program);
+
 pub struct Optimizer {
     program: Program,
     allow_const_out: bool,
+    budget: Option<Duration>,
+    const_output_with_input: Option<Vec<u8>>,
+}
+
+/// Things the optimizer found out that might interest the user.
+#[derive(Default, Debug)]
+pub struct OptStats {
+    /// The constant output pass was disabled, but would have been possible.
+    pub const_out_skipped: bool,
+    /// Time spent in the transforming passes.
+    pub budget_used: Duration,
+    /// Number of transforming passes skipped because the budget was exceeded.
+    pub passes_skipped: u32,
 }
 
 fn n(i: u32) -> Box<Expr> {
     Box::new(Expr::Num(VType::I32, i))
 }
 
+/// Make a number from a folded binary operation.  Like `Val::from_u32`, it is
+/// 16-bit if it fits, but only if both operands were 16-bit as well.
+fn folded_num(vt: VType, wt: VType, z: u32) -> Expr {
+    if vt == VType::I16 && wt == VType::I16 && z <= (u16::MAX as u32) {
+        Expr::Num(VType::I16, z)
+    } else {
+        Expr::Num(VType::I32, z)
+    }
+}
+
+/// Estimate the cost of evaluating an expression.  INTERCAL operators are
+/// expensive, native operators are cheap.
+fn expr_cost(e: &Expr) -> u32 {
+    match *e {
+        Expr::Num(..) => 0,
+        Expr::Var(..) => 1,
+        Expr::Mingle(ref vx, ref wx) |
+        Expr::Select(_, ref vx, ref wx) => 10 + expr_cost(vx) + expr_cost(wx),
+        Expr::And(_, ref vx) |
+        Expr::Or(_, ref vx) |
+        Expr::Xor(_, ref vx) => 5 + expr_cost(vx),
+        Expr::RsNot(ref vx) => 1 + expr_cost(vx),
+        Expr::RsAnd(ref vx, ref wx) |
+        Expr::RsOr(ref vx, ref wx) |
+        Expr::RsXor(ref vx, ref wx) |
+        Expr::RsRshift(ref vx, ref wx) |
+        Expr::RsLshift(ref vx, ref wx) |
+        Expr::RsNotEqual(ref vx, ref wx) |
+        Expr::RsPlus(ref vx, ref wx) |
+        Expr::RsMinus(ref vx, ref wx) => 2 + expr_cost(vx) + expr_cost(wx),
+    }
+}
+
 impl Optimizer {
     pub fn new(program: Program, allow_const_out: bool) -> Optimizer {
-        Optimizer { program, allow_const_out }
+        Optimizer { program, allow_const_out, budget: None, const_output_with_input: None }
     }
 
-    pub fn optimize(self) -> Program {
-        let mut program = self.program;
-        program = Optimizer::opt_constant_fold(program);
-        program = Optimizer::opt_expressions(program);
-        if self.allow_const_out {
-            program = Optimizer::opt_const_output(program);
-        }
-        program = Optimizer::opt_abstain_check(program);
-        program = Optimizer::opt_var_check(program);
-        program
+    /// Specialize the program for a fixed input: like the constant output pass,
+    /// but programs that take input are run with this input, and reduced to
+    /// the output they produce for it.
+    pub fn set_fixed_input(&mut self, input: Vec<u8>) {
+        self.const_output_with_input = Some(input);
     }
 
-    /// Fold expressions with literal constants, of which there are typically a lot
-    /// since you can't have 32-bit literals.
-    pub fn opt_constant_fold(mut program: Program) -> Program {
-        for stmt in &mut program.stmts {
-            match stmt.body {
-                StmtBody::Calc(_, ref mut expr) |
-                StmtBody::Resume(ref mut expr) |
-                StmtBody::Forget(ref mut expr) => Optimizer::fold(expr),
-                _ => { }
-            }
+    /// Limit the time spent in the transforming passes; once it is exceeded, the
+    /// remaining ones are skipped.  The analysis passes needed by the code
+    /// generator always run.
+    pub fn set_budget(&mut self, budget: Duration) {
+        self.budget = Some(budget);
+    }
+
+    /// The transforming passes that `optimize` runs, in order.  The constant
+    /// output pass with a fixed input is not included, it always runs last.
+    pub fn transforming_passes(&self) -> Vec<Pass> {
+        let mut passes: Vec<Pass> = vec![("floatlib stripping", Optimizer::opt_strip_floatlib),
+                                         ("constant folding", Optimizer::opt_constant_fold),
+                                         ("expressions", Optimizer::opt_expressions)];
+        if self.const_output_with_input.is_none() && self.allow_const_out {
+            passes.push(("constant output", Optimizer::opt_const_output));
         }
-        program
+        passes
     }
 
-    fn fold(expr: &mut Expr) {
-        let mut result = None;
-        match *expr {
-            Expr::Mingle(ref mut vx, ref mut wx) => {
-                Optimizer::fold(vx);
-                Optimizer::fold(wx);
-                if let Expr::Num(_, v) = **vx {
-                    if let Expr::Num(_, w) = **wx {
-                        if v <= (u16::MAX as u32) &&
-                           w <= (u16::MAX as u32) {
-                            let z = mingle(v, w);
-                            result = Some(*n(z));
-                        }
-                    }
-                }
-            }
-            Expr::Select(_, ref mut vx, ref mut wx) => {
-                Optimizer::fold(vx);
-                Optimizer::fold(wx);
-                if let Expr::Num(_, v) = **vx {
-                    if let Expr::Num(_, w) = **wx {
-                        let z = select(v, w);
-                        result = Some(*n(z));
-                    }
-                }
-            }
-            Expr::And(_, ref mut vx) => {
-                Optimizer::fold(vx);
-                if let Expr::Num(vtype, v) = **vx {
-                    result = Some(match vtype {
-                        VType::I16 => Expr::Num(vtype, and_16(v)),
-                        VType::I32 => Expr::Num(vtype, and_32(v)),
-                    });
-                }
-            }
-            Expr::Or(_, ref mut vx) => {
-                Optimizer::fold(vx);
-                if let Expr::Num(vtype, v) = **vx {
-                    result = Some(match vtype {
-                        VType::I16 => Expr::Num(vtype, or_16(v)),
-                        VType::I32 => Expr::Num(vtype, or_32(v)),
-                    });
-                }
-            }
-            Expr::Xor(_, ref mut vx) => {
-                Optimizer::fold(vx);
-                if let Expr::Num(vtype, v) = **vx {
-                    result = Some(match vtype {
-                        VType::I16 => Expr::Num(vtype, xor_16(v)),
-                        VType::I32 => Expr::Num(vtype, xor_32(v)),
-                    });
-                }
-            }
-            _ => {}
+    /// All passes that `optimize` runs, including the analysis passes that
+    /// always run last.  The constant output pass with a fixed input (see
+    /// `set_fixed_input`) needs the input, so it is not a `Pass` and is left out.
+    #[allow(dead_code)]  // for diagnosing miscompiles
+    pub fn passes(&self) -> Vec<Pass> {
+        let mut passes = self.transforming_passes();
+        passes.push(("abstain check", Optimizer::opt_abstain_check));
+        passes.push(("var check", Optimizer::opt_var_check));
+        passes
+    }
+
+    /// Bisect a miscompile: apply the given passes one at a time, run the
+    /// program with `input` after each of them, and return the name of the
+    /// first pass after which the output (or error) differs from that of the
+    /// unoptimized program.  The program must terminate for this to work.
+    #[allow(dead_code)]  // for diagnosing miscompiles
+    pub fn find_diverging_pass(&self, input: &[u8], passes: &[Pass]) -> Option<&'static str> {
+        fn output(program: &Program, input: &[u8]) -> Result<Vec<u8>, String> {
+            eval::Eval::run(program, input, Some(0)).map(|r| r.0).map_err(|e| e.to_string())
         }
-        if let Some(result) = result {
-            *expr = result;
+        let baseline = output(&self.program, input);
+        let mut program = self.program.clone();
+        for &(name, pass) in passes {
+            program = pass(program);
+            if output(&program, input) != baseline {
+                return Some(name);
+            }
         }
+        None
     }
 
-    /// Optimize expressions.
-    pub fn opt_expressions(mut program: Program) -> Program {
-        for stmt in &mut program.stmts {
-            //println!("\n\n{}", stmt.props.srcline);
-            match stmt.body {
-                StmtBody::Calc(_, ref mut expr) |
-                StmtBody::Resume(ref mut expr) |
-                StmtBody::Forget(ref mut expr) => Optimizer::opt_expr(expr),
-                _ => { }
+    /// Optimize, and also report what the optimizer found out.
+    pub fn optimize(self) -> (Program, OptStats) {
+        let mut stats = OptStats::default();
+        let passes = self.transforming_passes();
+        let mut program = self.program;
+        if self.const_output_with_input.is_none() && !self.allow_const_out {
+            stats.const_out_skipped = Optimizer::const_output_possible(&program, false);
+        }
+        let t0 = Instant::now();
+        for (_, pass) in passes {
+            if self.budget.is_some_and(|budget| t0.elapsed() >= budget) {
+                stats.passes_skipped += 1;
+            } else {
+                program = pass(program);
             }
         }
-        program
+        if let Some(input) = self.const_output_with_input {
+            if self.budget.is_some_and(|budget| t0.elapsed() >= budget) {
+                stats.passes_skipped += 1;
+            } else {
+                program = Optimizer::const_output(program, Some(&input));
+            }
+        }
+        stats.budget_used = t0.elapsed();
+        // these must always run
+        program = Optimizer::opt_abstain_check(program);
+        program = Optimizer::opt_var_check(program);
+        (program, stats)
     }
 
-    fn opt_expr(expr: &mut Expr) {
-        //println!("optimizing {}", expr);
-        let mut result = None;
-        match *expr {
-            Expr::Select(_, ref mut vx, ref mut wx) => {
-                Optimizer::opt_expr(vx);
-                Optimizer::opt_expr(wx);
-                match **wx {
-                    // Select(UnOP(Mingle(x, y)), 0x5555_5555) = BinOP(x, y)
-                    Expr::Num(_, 0x5555_5555) => {
-                        match **vx {
-                            Expr::And(_, box Expr::Mingle(ref m1, ref m2)) => {
-                                result = Some(Expr::RsAnd(m1.clone(), m2.clone()));
-                            }
-                            Expr::Or(_, box Expr::Mingle(ref m1, ref m2)) => {
-                                result = Some(Expr::RsOr(m1.clone(), m2.clone()));
-                            }
-                            Expr::Xor(_, box Expr::Mingle(ref m1, ref m2)) => {
-                                result = Some(Expr::RsXor(m1.clone(), m2.clone()));
-                            }
-                            _ => { }
-                        }
-                    }
-                    // Select(x, N) is a shift & mask if N has to "inside" zeros
-                    // in binary notation
-                    Expr::Num(_, i) if i.count_zeros() == i.leading_zeros() + i.trailing_zeros() => {
-                        if i.trailing_zeros() == 0 {
-                            result = Some(Expr::RsAnd(vx.clone(), n(i)));
-                        } else if i.leading_zeros() == 0 {
-                            result = Some(Expr::RsRshift(vx.clone(), n(i.trailing_zeros())));
-                        } else {
-                            result = Some(Expr::RsAnd(
-                                Box::new(Expr::RsRshift(vx.clone(), n(i.trailing_zeros()))),
-                                n((1 << i.count_ones()) - 1)));
-                        }
-                    }
-                    // Select(Mingle(x, 0), 0x2AAA_AAAB)  ->  (x << 1) & 0xFFFF
-                    Expr::Num(_, 0x2AAA_AAAB) => {
-                        if let Expr::Mingle(ref m1, box Expr::Num(_, 0)) = **vx {
-                            result = Some(Expr::RsAnd(
-                                Box::new(Expr::RsLshift(m1.clone(), n(1))), n(0xFFFF)));
-                        }
-                    }
-                    _ => { }
-                }
+    /// Remove the floatlib again if the parser added it, but none of its
+    /// routines can actually be reached: the NEXTs to it are all in dead code.
+    pub fn opt_strip_floatlib(mut program: Program) -> Program {
+        // with computed COME FROMs, we can't tell where control goes
+        if program.uses_complex_comefrom {
+            return program;
+        }
+        // after Parser::concat, the floatlib can be somewhere in the middle;
+        // then leave it alone
+        let start = match program.floatlib_start {
+            Some(start) => start,
+            None => return program,
+        };
+        // find all statements that control can get to; abstentions can be
+        // undone, so they don't count
+        let mut reached = vec![false; program.stmts.len()];
+        let mut todo = vec![0];
+        while let Some(i) = todo.pop() {
+            if i >= program.stmts.len() || reached[i] {
+                continue;
             }
-            Expr::Mingle(ref mut vx, ref mut wx) => {
-                Optimizer::opt_expr(vx);
-                Optimizer::opt_expr(wx);
-                // (x ~ 0xA..A) OP (y ~ 0xA..A) $ (x ~ 0x5..5) OP (y ~ 0x5..5)
-                // -> (x OP y) in 32-bit
-                if let Expr::RsAnd(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
-                                   box Expr::Select(_, ref bx, box Expr::Num(_, 0xAAAA_AAAA))) = **vx {
-                    if let Expr::RsAnd(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
-                                       box Expr::Select(_, ref dx, box Expr::Num(_, 0x5555_5555))) = **wx {
-                        if *ax == *cx && *bx == *dx {
-                            result = Some(Expr::RsAnd(ax.clone(), bx.clone()));
-                        }
-                    }
-                }
-                if let Expr::RsOr(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
-                                  box Expr::Select(_, ref bx, box Expr::Num(_, 0xAAAA_AAAA))) = **vx {
-                    if let Expr::RsOr(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
-                                      box Expr::Select(_, ref dx, box Expr::Num(_, 0x5555_5555))) = **wx {
-                        if *ax == *cx && *bx == *dx {
-                            result = Some(Expr::RsOr(ax.clone(), bx.clone()));
-                        }
-                    }
-                }
-                if let Expr::RsXor(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
-                                   box Expr::Select(_, ref bx, box Expr::Num(_, 0xAAAA_AAAA))) = **vx {
-                    if let Expr::RsXor(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
-                                       box Expr::Select(_, ref dx, box Expr::Num(_, 0x5555_5555))) = **wx {
-                        if *ax == *cx && *bx == *dx {
-                            result = Some(Expr::RsXor(ax.clone(), bx.clone()));
-                        }
-                    }
-                }
-                // (x ~ 0xA..A) OP y1 $ (x ~ 0x5..5) OP y2
-                // -> (x OP (y1 << 16 | y2)) in 32-bit
-                if let Expr::RsAnd(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
-                                   box Expr::Num(_, bn)) = **vx {
-                    if let Expr::RsAnd(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
-                                       box Expr::Num(_, dn)) = **wx {
-                        if *ax == *cx {
-                            result = Some(Expr::RsAnd(ax.clone(), n((bn << 16) | dn)));
-                        }
-                    }
-                }
-                if let Expr::RsOr(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
-                                  box Expr::Num(_, bn)) = **vx {
-                    if let Expr::RsOr(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
-                                      box Expr::Num(_, dn)) = **wx {
-                        if *ax == *cx {
-                            result = Some(Expr::RsOr(ax.clone(), n((bn << 16) | dn)));
-                        }
-                    }
-                }
-                if let Expr::RsXor(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
-                                   box Expr::Num(_, bn)) = **vx {
-                    if let Expr::RsXor(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
-                                       box Expr::Num(_, dn)) = **wx {
-                        if *ax == *cx {
-                            result = Some(Expr::RsXor(ax.clone(), n((bn << 16) | dn)));
-                        }
-                    }
-                }
-                // (x != y) $ (z != w)  ->  ((x != y) << 1) | (z != w)
-                if let Expr::RsNotEqual(..) = **vx {
-                    if let Expr::RsNotEqual(..) = **wx {
-                        result = Some(Expr::RsOr(Box::new(Expr::RsLshift(vx.clone(), n(1))), wx.clone()));
+            reached[i] = true;
+            let stmt = &program.stmts[i];
+            match stmt.body {
+                StmtBody::GiveUp => { }
+                StmtBody::TryAgain => todo.push(0),
+                StmtBody::DoNext(n) => {
+                    if let Some(&target) = program.labels.get(&n) {
+                        todo.push(target as usize);
                     }
+                    // a RESUME can come back here
+                    todo.push(i + 1);
                 }
+                _ => todo.push(i + 1),
             }
-            Expr::And(_, ref mut vx) | Expr::Or(_, ref mut vx) | Expr::Xor(_, ref mut vx) |
-            Expr::RsNot(ref mut vx) => {
-                Optimizer::opt_expr(vx);
+            if let Some(next) = stmt.comefrom {
+                todo.push(next as usize);
             }
-            Expr::RsAnd(ref mut vx, ref mut wx) => {
-                Optimizer::opt_expr(vx);
-                Optimizer::opt_expr(wx);
-                // (x ~ x) & 1  ->  x != 0
-                if let Expr::Select(_, ref sx, ref tx) = **vx {
-                    if *sx == *tx {
-                        if let Expr::Num(_, 1) = **wx {
-                            result = Some(Expr::RsNotEqual(sx.clone(), n(0)));
-                        }
-                    }
-                }
-                // ?(x $ 1) & 3  ->  1 + (x & 1)
-                if let Expr::Xor(_, box Expr::Mingle(ref mx, box Expr::Num(_, 1))) = **vx {
-                    if let Expr::Num(_, 3) = **wx {
-                        result = Some(Expr::RsPlus(n(1), Box::new(Expr::RsAnd(mx.clone(), n(1)))));
-                    }
-                }
-                // ?(x $ 2) & 3  ->  2 - (x & 1)
-                if let Expr::Xor(_, box Expr::Mingle(ref mx, box Expr::Num(_, 2))) = **vx {
-                    if let Expr::Num(_, 3) = **wx {
-                        result = Some(Expr::RsMinus(n(2), Box::new(Expr::RsAnd(mx.clone(), n(1)))));
-                    }
-                }
-                // x & 0xFFFFFFFF has no effect
-                if let Expr::Num(_, 0xFFFF_FFFF) = **wx {
-                    result = Some(*vx.clone());
-                }
-                // Select(UnOP(Mingle(x, y)), 1) = BinOP(x & 1, y & 1)
-                if let Expr::Num(_, 1) = **wx {
-                    match **vx {
-                        Expr::And(_, box Expr::Mingle(ref m1, ref m2)) => {
-                            result = Some(Expr::RsAnd(
-                                Box::new(Expr::RsAnd(m1.clone(), n(1))),
-                                Box::new(Expr::RsAnd(m2.clone(), n(1)))));
-                        }
-                        Expr::Or(_, box Expr::Mingle(ref m1, ref m2)) => {
-                            result = Some(Expr::RsOr(
-                                Box::new(Expr::RsAnd(m1.clone(), n(1))),
-                                Box::new(Expr::RsAnd(m2.clone(), n(1)))));
-                        }
-                        Expr::Xor(_, box Expr::Mingle(ref m1, ref m2)) => {
-                            result = Some(Expr::RsXor(
-                                Box::new(Expr::RsAnd(m1.clone(), n(1))),
-                                Box::new(Expr::RsAnd(m2.clone(), n(1)))));
-                        }
-                        _ => { }
-                    }
-                }
-                // ((x & y) & y)  ->  second & has no effect
-                if let Expr::RsAnd(_, ref v2x) = **vx {
-                    if *v2x == *wx {
-                        result = Some(*vx.clone());
-                    }
-                }
-                // ((x != y) & 1)  ->  & has no effect
-                if let Expr::RsNotEqual(..) = **vx {
-                    if let Expr::Num(_, 1) = **wx {
-                        result = Some(*vx.clone());
-                    }
-                }
-            }
-            Expr::RsXor(ref mut vx, ref mut wx) => {
-                Optimizer::opt_expr(vx);
-                Optimizer::opt_expr(wx);
-                if let Expr::Num(_, 0xFFFF_FFFF) = **wx {
-                    result = Some(Expr::RsNot(vx.clone()));
-                }
-                else if let Expr::Num(_, 0xFFFF_FFFF) = **vx {
-                    result = Some(Expr::RsNot(wx.clone()));
+        }
+        if reached[start..].iter().any(|&r| r) {
+            return program;
+        }
+        program.stmts.truncate(start);
+        program.stmt_types.truncate(start);
+        program.labels.retain(|_, &mut idx| (idx as usize) < start);
+        // the dead NEXTs now go nowhere; the code generator would reject them,
+        // so make them fail like such a NEXT does in the interpreter
+        for stmt in &mut program.stmts {
+            if let StmtBody::DoNext(n) = stmt.body {
+                if !program.labels.contains_key(&n) {
+                    let orig = format!("({}) NEXT", n);
+                    stmt.body = StmtBody::Error(IE129.new(Some(orig), stmt.props.onthewayto));
                 }
             }
-            Expr::RsOr(ref mut vx, ref mut wx) |
-            Expr::RsRshift(ref mut vx, ref mut wx) |
-            Expr::RsLshift(ref mut vx, ref mut wx) |
-            // Expr::RsEqual(ref mut vx, ref mut wx) |
-            Expr::RsNotEqual(ref mut vx, ref mut wx) |
-            Expr::RsMinus(ref mut vx, ref mut wx) |
-            Expr::RsPlus(ref mut vx, ref mut wx) => {
-                Optimizer::opt_expr(vx);
-                Optimizer::opt_expr(wx);
-            }
-            Expr::Num(..) | Expr::Var(..) => { }
-        }
-        if let Some(mut result) = result {
-            Optimizer::opt_expr(&mut result);  // XXX will this always terminate?
-            *expr = result;
         }
+        program.added_floatlib = false;
+        program.floatlib_start = None;
+        program
     }
 
-    /// Cleverly check for programs that don't take input and always produce the
-    /// same output; reduce them to a Print statement.
-    pub fn opt_const_output(program: Program) -> Program {
-        let mut possible = true;
+    /// Fold expressions with literal constants, of which there are typically a lot
+    /// since you can't have 32-bit literals.
+    pub fn opt_constant_fold(mut program: Program) -> Program {
+        ConstantFolder.walk_program(&mut program);
+        program
+    }
+
+    /// Optimize expressions.
+    pub fn opt_expressions(program: Program) -> Program {
+        Optimizer::opt_expressions_with(program, PEEPHOLES)
+    }
+
+    /// Optimize expressions with the given peephole rules instead of the
+    /// built-in ones.
+    pub fn opt_expressions_with(mut program: Program, rules: &[Peephole]) -> Program {
+        PeepholeOptimizer(rules).walk_program(&mut program);
+        program
+    }
+
+    /// Check if the program neither takes input (unless it is fixed) nor uses
+    /// random numbers.  (It can still fail to terminate.)
+    fn const_output_possible(program: &Program, fixed_input: bool) -> bool {
         let mut prev_lbl = 0;
         for stmt in &program.stmts {
             // if we have a statement with %, no chance
@@ -379,47 +294,56 @@ impl Optimizer {
                 if !(program.added_syslib && prev_lbl == 1901) {
                     if !(program.added_floatlib &&
                          (prev_lbl == 5401 || prev_lbl == 5402)) {
-                        possible = false;
-                        break;
+                        return false;
                     }
                 }
             }
             match stmt.body {
                 // if we accept input, bail out
-                StmtBody::WriteIn(..) => {
-                    possible = false;
-                    break;
-                }
+                StmtBody::WriteIn(..) if !fixed_input => return false,
                 // if we call one of the stdlib random routines, bail out
+                // (see syslib.rs)
                 StmtBody::DoNext(n) if ((n == 1900 || n == 1910 || n == 5400) &&
-                                        prev_lbl != 1911) => {
-                    possible = false;
-                    break;
-                }
+                                        prev_lbl != 1911) => return false,
                 _ => { }
             }
             prev_lbl = stmt.props.label;
         }
-        if !possible {
+        true
+    }
+
+    /// Cleverly check for programs that don't take input and always produce the
+    /// same output; reduce them to a Print statement.
+    pub fn opt_const_output(program: Program) -> Program {
+        Optimizer::const_output(program, None)
+    }
+
+    /// The constant output pass, with a fixed `input` fed to the program if
+    /// given.  Otherwise the program must not take any.
+    fn const_output(program: Program, input: Option<&[u8]>) -> Program {
+        if !Optimizer::const_output_possible(&program, input.is_some()) {
             return program;
         }
         // we can do it! evaluate the program and replace all statements
-        let out = Vec::new();
-        let mut cursor = Cursor::new(out);
-        if eval::Eval::new(&program, &mut cursor, false, false).eval().is_err() {
-            // if eval fails, don't pretend to do anything.
-            return program;
-        }
+        let out = match eval::Eval::run(&program, input.unwrap_or(&[]), Some(0)) {
+            Ok((out, outcome)) if outcome.termination != eval::Termination::LimitReached => out,
+            // if eval fails or does not finish, don't pretend to do anything.
+            _ => return program,
+        };
+        let stmts = vec![Stmt::new_with(StmtBody::Print(out)),
+                         Stmt::new_with(StmtBody::GiveUp)];
+        // keep one type per statement, the other passes rely on that
+        let stmt_types = stmts.iter().map(Stmt::stype).collect();
         Program {
-            stmts: vec![Stmt::new_with(StmtBody::Print(cursor.into_inner())),
-                        Stmt::new_with(StmtBody::GiveUp)],
+            stmts,
             labels: BTreeMap::new(),
-            stmt_types: vec![Abstain::Label(0)],
+            stmt_types,
             var_info: (vec![], vec![], vec![], vec![]),
             uses_complex_comefrom: false,
             added_syslib: false,
             added_floatlib: false,
-            bugline: 2
+            floatlib_start: None,
+            bugline: 2  // past the end, so no compiler bug
         }
     }
 
@@ -432,8 +356,10 @@ impl Optimizer {
                 StmtBody::Reinstate(ref whats) => {
                     for what in whats {
                         if let Abstain::Label(lbl) = *what {
-                            let idx = program.labels[&lbl];
-                            can_abstain[idx as usize] = true;
+                            // dangling labels are reported by check_abstain_labels
+                            if let Some(&idx) = program.labels.get(&lbl) {
+                                can_abstain[idx as usize] = true;
+                            }
                         } else {
                             for (i, stype) in program.stmt_types.iter().enumerate() {
                                 if stype == what {
@@ -466,33 +392,485 @@ impl Optimizer {
         reset(&mut program.var_info.1);
         reset(&mut program.var_info.2);
         reset(&mut program.var_info.3);
+        let Program { ref mut stmts, ref mut var_info, .. } = program;
+        let mut checker = VarChecker { var_info, stype: Abstain::Label(0) };
+        for stmt in stmts {
+            checker.visit_stmt(stmt);
+        }
+        program
+    }
+
+    /// Find RETRIEVEs of variables that are never STASHed anywhere, so that they
+    /// must fail with E436.  With COME FROM around, a real flow analysis is
+    /// hopeless, so this only catches the obvious cases.
+    pub fn check_stash_balance(program: &Program) -> Vec<(Var, SrcLine)> {
+        let mut stashed = (vec![false; program.var_info.0.len()],
+                           vec![false; program.var_info.1.len()],
+                           vec![false; program.var_info.2.len()],
+                           vec![false; program.var_info.3.len()]);
         for stmt in &program.stmts {
-            match stmt.body {
-                StmtBody::Stash(ref vars) |
-                StmtBody::Retrieve(ref vars) => {
-                    for var in vars {
-                        match *var {
-                            Var::I16(n) => program.var_info.0[n].can_stash = true,
-                            Var::I32(n) => program.var_info.1[n].can_stash = true,
-                            Var::A16(n, _) => program.var_info.2[n].can_stash = true,
-                            Var::A32(n, _) => program.var_info.3[n].can_stash = true,
-                        }
+            if let StmtBody::Stash(ref vars) = stmt.body {
+                for var in vars {
+                    match *var {
+                        Var::I16(n) => stashed.0[n] = true,
+                        Var::I32(n) => stashed.1[n] = true,
+                        Var::A16(n, _) => stashed.2[n] = true,
+                        Var::A32(n, _) => stashed.3[n] = true,
+                    }
+                }
+            }
+        }
+        let mut suspicious = Vec::new();
+        for stmt in &program.stmts {
+            if let StmtBody::Retrieve(ref vars) = stmt.body {
+                for var in vars {
+                    let is_stashed = match *var {
+                        Var::I16(n) => stashed.0[n],
+                        Var::I32(n) => stashed.1[n],
+                        Var::A16(n, _) => stashed.2[n],
+                        Var::A32(n, _) => stashed.3[n],
+                    };
+                    if !is_stashed {
+                        suspicious.push((var.clone(), stmt.props.srcline));
                     }
                 }
-                StmtBody::Ignore(ref vars) |
-                StmtBody::Remember(ref vars) => {
-                    for var in vars {
-                        match *var {
-                            Var::I16(n) => program.var_info.0[n].can_ignore = true,
-                            Var::I32(n) => program.var_info.1[n].can_ignore = true,
-                            Var::A16(n, _) => program.var_info.2[n].can_ignore = true,
-                            Var::A32(n, _) => program.var_info.3[n].can_ignore = true,
+            }
+        }
+        suspicious
+    }
+
+    /// Render the listings of a program before and after optimizing like a
+    /// unified diff: statements are grouped by their source line, and lines
+    /// whose statements differ get the old ones with "-" and the new ones with
+    /// "+" in front.  Statements made up by the optimizer have source line 0.
+    #[allow(dead_code)]  // for understanding what the optimizer did
+    pub fn listing_diff(before: &Program, after: &Program) -> String {
+        let mut lines: BTreeMap<SrcLine, (Vec<String>, Vec<String>)> = BTreeMap::new();
+        for stmt in &before.stmts {
+            lines.entry(stmt.props.srcline).or_default().0.push(stmt.to_string());
+        }
+        for stmt in &after.stmts {
+            lines.entry(stmt.props.srcline).or_default().1.push(stmt.to_string());
+        }
+        let mut diff = String::new();
+        for (old, new) in lines.values() {
+            if old == new {
+                for line in old {
+                    diff.push_str(&format!("  {}\n", line));
+                }
+            } else {
+                for line in old {
+                    diff.push_str(&format!("- {}\n", line));
+                }
+                for line in new {
+                    diff.push_str(&format!("+ {}\n", line));
+                }
+            }
+        }
+        diff
+    }
+
+    /// Find ABSTAINs and REINSTATEs of labels that no statement has.  The parser
+    /// already rejects such ABSTAINs with E139, but lets REINSTATEs through.
+    pub fn check_abstain_labels(program: &Program) -> Vec<(Label, SrcLine)> {
+        let mut dangling = Vec::new();
+        for stmt in &program.stmts {
+            match stmt.body {
+                StmtBody::Abstain(_, ref whats) |
+                StmtBody::Reinstate(ref whats) => {
+                    for what in whats {
+                        if let Abstain::Label(lbl) = *what {
+                            if !program.labels.contains_key(&lbl) {
+                                dangling.push((lbl, stmt.props.srcline));
+                            }
                         }
                     }
                 }
                 _ => { }
             }
         }
-        program
+        dangling
+    }
+}
+
+
+/// Marks the variables that are STASHed or IGNOREd somewhere.
+struct VarChecker<'a> {
+    var_info: &'a mut (Vec<VarInfo>, Vec<VarInfo>, Vec<VarInfo>, Vec<VarInfo>),
+    stype: Abstain,
+}
+
+impl<'a> ExprVisitor for VarChecker<'a> {
+    fn visit_var(&mut self, var: &mut Var) {
+        let info = match *var {
+            Var::I16(n) => &mut self.var_info.0[n],
+            Var::I32(n) => &mut self.var_info.1[n],
+            Var::A16(n, _) => &mut self.var_info.2[n],
+            Var::A32(n, _) => &mut self.var_info.3[n],
+        };
+        if let Abstain::Stash | Abstain::Retrieve = self.stype {
+            info.can_stash = true;
+        } else {
+            info.can_ignore = true;
+        }
+    }
+}
+
+impl<'a> StmtVisitor for VarChecker<'a> {
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        self.stype = stmt.stype();
+        if let Abstain::Stash | Abstain::Retrieve | Abstain::Ignore | Abstain::Remember =
+            self.stype
+        {
+            self.walk_stmt(stmt);
+        }
+    }
+}
+
+/// Folds operators with only constant operands into constants.
+struct ConstantFolder;
+
+impl ExprVisitor for ConstantFolder {
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        let result = match *expr {
+            Expr::Mingle(box Expr::Num(vt, v), box Expr::Num(wt, w)) => {
+                // on overflow, leave it to the runtime to complain
+                mingle(v, w, 0).ok().map(|z| folded_num(vt, wt, z))
+            }
+            Expr::Select(_, box Expr::Num(vt, v), box Expr::Num(wt, w)) =>
+                Some(folded_num(vt, wt, select(v, w))),
+            // the width of unary operators is the one the parser gave them from
+            // their operand as written: a mingle operand makes them 32-bit, even
+            // if it has been folded into a 16-bit number by now
+            Expr::And(vtype, box Expr::Num(_, v)) => Some(match vtype {
+                VType::I16 => Expr::Num(vtype, and_16(v)),
+                VType::I32 => Expr::Num(vtype, and_32(v)),
+            }),
+            Expr::Or(vtype, box Expr::Num(_, v)) => Some(match vtype {
+                VType::I16 => Expr::Num(vtype, or_16(v)),
+                VType::I32 => Expr::Num(vtype, or_32(v)),
+            }),
+            Expr::Xor(vtype, box Expr::Num(_, v)) => Some(match vtype {
+                VType::I16 => Expr::Num(vtype, xor_16(v)),
+                VType::I32 => Expr::Num(vtype, xor_32(v)),
+            }),
+            _ => None,
+        };
+        if let Some(result) = result {
+            *expr = result;
+        }
+    }
+}
+
+impl StmtVisitor for ConstantFolder { }
+
+/// Applies the given peephole rules to expressions, bottom-up.
+struct PeepholeOptimizer<'a>(&'a [Peephole]);
+
+impl<'a> ExprVisitor for PeepholeOptimizer<'a> {
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        if let Some(mut result) = self.0.iter().find_map(|rule| rule(expr)) {
+            self.walk_expr(&mut result);
+            // only commit to rewrites that make the expression strictly cheaper
+            if expr_cost(&result) < expr_cost(expr) {
+                *expr = result;
+            }
+        }
+    }
+}
+
+impl<'a> StmtVisitor for PeepholeOptimizer<'a> { }
+
+/// A peephole rule of the expression optimizer: gets an expression whose
+/// operands are already optimized, and returns an equivalent expression if the
+/// rule applies to it.
+pub type Peephole = fn(&Expr) -> Option<Expr>;
+
+/// The rules tried by the expression optimizer, in order; the first one that
+/// applies wins.
+const PEEPHOLES: &[Peephole] = &[
+    rewrite_select_mingle,
+    rewrite_select_unmingle,
+    rewrite_select_all,
+    rewrite_select_shift_mask,
+    rewrite_select_mingle_zero,
+    rewrite_mingle_pair,
+    rewrite_mingle_const_pair,
+    rewrite_mingle_not_equal,
+    rewrite_and_self_select,
+    rewrite_and_xor_mingle,
+    rewrite_and_mask,
+    rewrite_and_one_mingle,
+    rewrite_and_twice,
+    rewrite_and_not_equal,
+    rewrite_xor_not,
+    rewrite_not_not,
+    rewrite_not_const,
+    rewrite_or_zero,
+    rewrite_same_operands,
+    rewrite_shift_merge,
+];
+
+/// Helper for merging two shifts in the same direction.
+fn merged_shift(shift: fn(Box<Expr>, Box<Expr>) -> Expr, x: &Expr, a: u32, b: u32) -> Expr {
+    match a.checked_add(b) {
+        Some(amount) if amount < 32 => shift(Box::new(x.clone()), n(amount)),
+        _ => Expr::Num(VType::I32, 0),
+    }
+}
+
+/// Select(UnOP(Mingle(x, y)), 0x5555_5555)  ->  BinOP(x, y)
+pub fn rewrite_select_mingle(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::Select(_, ref vx, box Expr::Num(_, 0x5555_5555)) => match **vx {
+            Expr::And(_, box Expr::Mingle(ref m1, ref m2)) =>
+                Some(Expr::RsAnd(m1.clone(), m2.clone())),
+            Expr::Or(_, box Expr::Mingle(ref m1, ref m2)) =>
+                Some(Expr::RsOr(m1.clone(), m2.clone())),
+            Expr::Xor(_, box Expr::Mingle(ref m1, ref m2)) =>
+                Some(Expr::RsXor(m1.clone(), m2.clone())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Select(Mingle(x, y), 0xAAAA_AAAA)  ->  x, and Select(Mingle(x, y), 0x5555_5555)  ->  y
+///
+/// Only for 16-bit operands, since the mingle must still fail on wider values.
+pub fn rewrite_select_unmingle(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::Select(_, box Expr::Mingle(ref m1, ref m2), box Expr::Num(_, mask))
+            if m1.get_vtype() == VType::I16 && m2.get_vtype() == VType::I16 => match mask {
+                0xAAAA_AAAA => Some(*m1.clone()),
+                0x5555_5555 => Some(*m2.clone()),
+                _ => None,
+            },
+        _ => None,
+    }
+}
+
+/// Select(x, 0xFFFFFFFF) selects all bits in place  ->  x
+pub fn rewrite_select_all(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::Select(_, ref vx, box Expr::Num(_, 0xFFFF_FFFF)) => Some(*vx.clone()),
+        _ => None,
+    }
+}
+
+/// Select(x, N) is a shift & mask if N has no "inside" zeros in binary notation
+pub fn rewrite_select_shift_mask(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::Select(_, ref vx, box Expr::Num(_, i))
+            if i.count_zeros() == i.leading_zeros() + i.trailing_zeros() => {
+            if i.trailing_zeros() == 0 {
+                Some(Expr::RsAnd(vx.clone(), n(i)))
+            } else if i.leading_zeros() == 0 {
+                Some(Expr::RsRshift(vx.clone(), n(i.trailing_zeros())))
+            } else {
+                Some(Expr::RsAnd(
+                    Box::new(Expr::RsRshift(vx.clone(), n(i.trailing_zeros()))),
+                    n((1 << i.count_ones()) - 1)))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Select(Mingle(x, 0), 0x2AAA_AAAB)  ->  (x << 1) & 0xFFFF
+pub fn rewrite_select_mingle_zero(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::Select(_, box Expr::Mingle(ref m1, box Expr::Num(_, 0)),
+                     box Expr::Num(_, 0x2AAA_AAAB)) =>
+            Some(Expr::RsAnd(Box::new(Expr::RsLshift(m1.clone(), n(1))), n(0xFFFF))),
+        _ => None,
+    }
+}
+
+/// (x ~ 0xA..A) OP (y ~ 0xA..A) $ (x ~ 0x5..5) OP (y ~ 0x5..5)  ->  (x OP y) in 32-bit
+///
+/// The x and y of both halves are compared structurally, so they need not be
+/// shared nodes; since the operands are optimized deterministically, equal
+/// source subexpressions always end up equal here.
+pub fn rewrite_mingle_pair(expr: &Expr) -> Option<Expr> {
+    let (vx, wx) = match *expr {
+        Expr::Mingle(ref vx, ref wx) => (vx, wx),
+        _ => return None,
+    };
+    match (&**vx, &**wx) {
+        (&Expr::RsAnd(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
+                      box Expr::Select(_, ref bx, box Expr::Num(_, 0xAAAA_AAAA))),
+         &Expr::RsAnd(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
+                      box Expr::Select(_, ref dx, box Expr::Num(_, 0x5555_5555))))
+            if *ax == *cx && *bx == *dx => Some(Expr::RsAnd(ax.clone(), bx.clone())),
+        (&Expr::RsOr(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
+                     box Expr::Select(_, ref bx, box Expr::Num(_, 0xAAAA_AAAA))),
+         &Expr::RsOr(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
+                     box Expr::Select(_, ref dx, box Expr::Num(_, 0x5555_5555))))
+            if *ax == *cx && *bx == *dx => Some(Expr::RsOr(ax.clone(), bx.clone())),
+        (&Expr::RsXor(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
+                      box Expr::Select(_, ref bx, box Expr::Num(_, 0xAAAA_AAAA))),
+         &Expr::RsXor(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
+                      box Expr::Select(_, ref dx, box Expr::Num(_, 0x5555_5555))))
+            if *ax == *cx && *bx == *dx => Some(Expr::RsXor(ax.clone(), bx.clone())),
+        _ => None,
+    }
+}
+
+/// (x ~ 0xA..A) OP y1 $ (x ~ 0x5..5) OP y2  ->  (x OP (y1 << 16 | y2)) in 32-bit
+pub fn rewrite_mingle_const_pair(expr: &Expr) -> Option<Expr> {
+    let (vx, wx) = match *expr {
+        Expr::Mingle(ref vx, ref wx) => (vx, wx),
+        _ => return None,
+    };
+    match (&**vx, &**wx) {
+        (&Expr::RsAnd(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
+                      box Expr::Num(_, bn)),
+         &Expr::RsAnd(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
+                      box Expr::Num(_, dn)))
+            if *ax == *cx => Some(Expr::RsAnd(ax.clone(), n((bn << 16) | dn))),
+        (&Expr::RsOr(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
+                     box Expr::Num(_, bn)),
+         &Expr::RsOr(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
+                     box Expr::Num(_, dn)))
+            if *ax == *cx => Some(Expr::RsOr(ax.clone(), n((bn << 16) | dn))),
+        (&Expr::RsXor(box Expr::Select(_, ref ax, box Expr::Num(_, 0xAAAA_AAAA)),
+                      box Expr::Num(_, bn)),
+         &Expr::RsXor(box Expr::Select(_, ref cx, box Expr::Num(_, 0x5555_5555)),
+                      box Expr::Num(_, dn)))
+            if *ax == *cx => Some(Expr::RsXor(ax.clone(), n((bn << 16) | dn))),
+        _ => None,
+    }
+}
+
+/// (x != y) $ (z != w)  ->  ((x != y) << 1) | (z != w)
+pub fn rewrite_mingle_not_equal(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::Mingle(ref vx @ box Expr::RsNotEqual(..), ref wx @ box Expr::RsNotEqual(..)) =>
+            Some(Expr::RsOr(Box::new(Expr::RsLshift(vx.clone(), n(1))), wx.clone())),
+        _ => None,
+    }
+}
+
+/// (x ~ x) & 1  ->  x != 0
+pub fn rewrite_and_self_select(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsAnd(box Expr::Select(_, ref sx, ref tx), box Expr::Num(_, 1)) if *sx == *tx =>
+            Some(Expr::RsNotEqual(sx.clone(), n(0))),
+        _ => None,
+    }
+}
+
+/// ?(x $ 1) & 3  ->  1 + (x & 1), and ?(x $ 2) & 3  ->  2 - (x & 1)
+pub fn rewrite_and_xor_mingle(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsAnd(box Expr::Xor(_, box Expr::Mingle(ref mx, box Expr::Num(_, 1))),
+                    box Expr::Num(_, 3)) =>
+            Some(Expr::RsPlus(n(1), Box::new(Expr::RsAnd(mx.clone(), n(1))))),
+        Expr::RsAnd(box Expr::Xor(_, box Expr::Mingle(ref mx, box Expr::Num(_, 2))),
+                    box Expr::Num(_, 3)) =>
+            Some(Expr::RsMinus(n(2), Box::new(Expr::RsAnd(mx.clone(), n(1))))),
+        _ => None,
+    }
+}
+
+/// x & 0xFFFFFFFF has no effect, on either side
+pub fn rewrite_and_mask(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsAnd(ref vx, box Expr::Num(_, 0xFFFF_FFFF)) => Some(*vx.clone()),
+        Expr::RsAnd(box Expr::Num(_, 0xFFFF_FFFF), ref wx) => Some(*wx.clone()),
+        _ => None,
+    }
+}
+
+/// Select(UnOP(Mingle(x, y)), 1)  ->  BinOP(x & 1, y & 1)
+pub fn rewrite_and_one_mingle(expr: &Expr) -> Option<Expr> {
+    let bit = |x: &Expr| Box::new(Expr::RsAnd(Box::new(x.clone()), n(1)));
+    match *expr {
+        Expr::RsAnd(ref vx, box Expr::Num(_, 1)) => match **vx {
+            Expr::And(_, box Expr::Mingle(ref m1, ref m2)) =>
+                Some(Expr::RsAnd(bit(m1), bit(m2))),
+            Expr::Or(_, box Expr::Mingle(ref m1, ref m2)) =>
+                Some(Expr::RsOr(bit(m1), bit(m2))),
+            Expr::Xor(_, box Expr::Mingle(ref m1, ref m2)) =>
+                Some(Expr::RsXor(bit(m1), bit(m2))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// ((x & y) & y)  ->  second & has no effect
+pub fn rewrite_and_twice(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsAnd(ref vx @ box Expr::RsAnd(_, ref v2x), ref wx) if *v2x == *wx =>
+            Some(*vx.clone()),
+        _ => None,
+    }
+}
+
+/// ((x != y) & 1)  ->  & has no effect
+pub fn rewrite_and_not_equal(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsAnd(ref vx @ box Expr::RsNotEqual(..), box Expr::Num(_, 1)) => Some(*vx.clone()),
+        _ => None,
+    }
+}
+
+/// x ^ 0xFFFFFFFF, on either side  ->  !x
+pub fn rewrite_xor_not(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsXor(ref vx, box Expr::Num(_, 0xFFFF_FFFF)) => Some(Expr::RsNot(vx.clone())),
+        Expr::RsXor(box Expr::Num(_, 0xFFFF_FFFF), ref wx) => Some(Expr::RsNot(wx.clone())),
+        _ => None,
+    }
+}
+
+/// !!x  ->  x
+pub fn rewrite_not_not(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsNot(box Expr::RsNot(ref vx)) => Some(*vx.clone()),
+        _ => None,
+    }
+}
+
+/// !c  ->  constant; the native NOT always works on 32 bits (see `Eval`), even
+/// if c is a 16-bit constant
+pub fn rewrite_not_const(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsNot(box Expr::Num(_, c)) => Some(Expr::Num(VType::I32, !c)),
+        _ => None,
+    }
+}
+
+/// x | 0 has no effect, on either side
+pub fn rewrite_or_zero(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsOr(ref vx, box Expr::Num(_, 0)) => Some(*vx.clone()),
+        Expr::RsOr(box Expr::Num(_, 0), ref wx) => Some(*wx.clone()),
+        _ => None,
+    }
+}
+
+/// x & x  ->  x, x | x  ->  x, x ^ x  ->  0; expressions have no side effects,
+/// so the operand need only be evaluated once
+pub fn rewrite_same_operands(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsAnd(ref vx, ref wx) |
+        Expr::RsOr(ref vx, ref wx) if *vx == *wx => Some(*vx.clone()),
+        Expr::RsXor(ref vx, ref wx) if *vx == *wx => Some(Expr::Num(VType::I32, 0)),
+        _ => None,
+    }
+}
+
+/// (x >> a) >> b  ->  x >> (a + b), likewise for <<, or 0 if that shifts out
+/// everything
+pub fn rewrite_shift_merge(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::RsRshift(box Expr::RsRshift(ref ix, box Expr::Num(_, a)), box Expr::Num(_, b)) =>
+            Some(merged_shift(Expr::RsRshift, ix, a, b)),
+        Expr::RsLshift(box Expr::RsLshift(ref ix, box Expr::Num(_, a)), box Expr::Num(_, b)) =>
+            Some(merged_shift(Expr::RsLshift, ix, a, b)),
+        _ => None,
     }
 }
@@ -24,14 +24,15 @@
 /// There are quite a few steps to do after parsing, which are done in the method
 /// called `post_process`.  It makes a list of statements into a "real" program.
 
-use std::collections::{BTreeMap, HashMap, hash_map::Entry as HEntry};
+use std::collections::{BTreeMap, HashMap, HashSet, hash_map::Entry as HEntry};
 use std::io::{Read, BufRead, BufReader, Cursor};
 use std::u16;
 use std::str;
 
 use rand::{self, Rng};
 
-use crate::ast::{self, Program, Stmt, StmtBody, StmtProps, Expr, Abstain, ComeFrom, Var, VType, VarInfo};
+use crate::ast::{self, Program, Stmt, StmtBody, StmtProps, Expr, Abstain, ComeFrom, Var, VType, VarInfo,
+                 ExprVisitor, StmtVisitor};
 use crate::err::{Res, RtError, ErrDesc, IE000, IE017, IE079, IE099, IE139, IE182, IE197, IE200,
                  IE444, IE555, IE993};
 use crate::lex::{SrcLine, SrcToken, Rule, Lexer, lex};
@@ -52,6 +53,7 @@ pub struct Parser<'p> {
     stash:  Vec<SrcToken>,  // used for backtracking
     startline: usize,
     allow_bug: bool,
+    stdlibs: bool,
 }
 
 
@@ -65,7 +67,16 @@ impl<'p> Parser<'p> {
                  tokens: lex(code, startline),
                  stash: Vec::new(),
                  startline,
-                 allow_bug }
+                 allow_bug,
+                 stdlibs: true }
+    }
+
+    /// Select whether the syslib and floatlib are appended automatically when
+    /// the program calls their labels (the default).  Turn this off for a
+    /// program that gets a shared, separately parsed library with `concat`.
+    #[allow(dead_code)]  // for embedders running many programs
+    pub fn set_stdlibs(&mut self, stdlibs: bool) {
+        self.stdlibs = stdlibs;
     }
 
     fn get_lines<T: Read>(mut reader: BufReader<T>) -> Vec<String> {
@@ -87,7 +98,110 @@ impl<'p> Parser<'p> {
         // parse all statements
         let stmts = self.parse()?;
         // collect some necessary values and return the Program
-        self.post_process(stmts)
+        let program = self.post_process(stmts)?;
+        Parser::validate_labels(&program)?;
+        Ok(program)
+    }
+
+    /// Append a separately parsed library (like the syslib) to a program, as if
+    /// it had been part of the source.  This way a library can be parsed once
+    /// and shared by many programs.
+    ///
+    /// The library goes *behind* the program, since execution starts with the
+    /// first statement.  The program must be parsed with `set_stdlibs` off, or
+    /// it already gets its own copy of a library it calls.  The library's labels
+    /// and statement indices are offset accordingly, variables with the same
+    /// source number are shared, and COME FROMs are resolved again over the
+    /// whole program, so that gerund COME FROMs also catch statements of the
+    /// other part.  Both parts should be unoptimized; run the optimizer on the
+    /// result.
+    #[allow(dead_code)]  // for embedders running many programs
+    pub fn concat(program: Program, lib: Program) -> Res<Program> {
+        fn merge(infos: &mut Vec<VarInfo>, lib_infos: Vec<VarInfo>) -> Vec<usize> {
+            lib_infos.into_iter().map(|li| {
+                match infos.iter().position(|vi| vi.orig == li.orig) {
+                    Some(j) => {
+                        infos[j].can_ignore |= li.can_ignore;
+                        infos[j].can_stash |= li.can_stash;
+                        j
+                    }
+                    None => {
+                        infos.push(li);
+                        infos.len() - 1
+                    }
+                }
+            }).collect()
+        }
+
+        let offset = program.stmts.len();
+        let mut stmts = program.stmts;
+        let mut labels = program.labels;
+        let mut stmt_types = program.stmt_types;
+        let mut var_info = program.var_info;
+        let maps = [merge(&mut var_info.0, lib.var_info.0),
+                    merge(&mut var_info.1, lib.var_info.1),
+                    merge(&mut var_info.2, lib.var_info.2),
+                    merge(&mut var_info.3, lib.var_info.3)];
+        if let (Some(last), Some(first)) = (stmts.last_mut(), lib.stmts.first()) {
+            // TRY AGAIN must still be the last statement in the file
+            if let StmtBody::TryAgain = last.body {
+                return Err(IE993.new(None, last.props.onthewayto));
+            }
+            last.props.onthewayto = first.props.srcline;
+            last.props.onthewaycol = first.props.srccol;
+        }
+        for mut stmt in lib.stmts {
+            EachVar(|var: &mut Var| {
+                let (vtype, n) = var.unique();
+                var.rename(maps[vtype as usize][n]);
+            }).visit_stmt(&mut stmt);
+            stmts.push(stmt);
+        }
+        for (label, line) in lib.labels {
+            let line = line + offset as u16;
+            if labels.insert(label, line).is_some() {
+                return Err(IE182.new(None, stmts[line as usize].props.onthewayto));
+            }
+        }
+        stmt_types.extend(lib.stmt_types);
+        let uses_complex_comefrom = Parser::resolve_comefroms(&mut stmts, &labels, &stmt_types)?;
+        let bugline = if (program.bugline as usize) < offset {
+            program.bugline
+        } else {
+            lib.bugline + offset as u16
+        };
+        Ok(Program { stmts,
+                     labels,
+                     stmt_types,
+                     var_info,
+                     uses_complex_comefrom,
+                     added_syslib: program.added_syslib || lib.added_syslib,
+                     added_floatlib: program.added_floatlib || lib.added_floatlib,
+                     // a floatlib of the first program is now in the middle
+                     floatlib_start: lib.floatlib_start.map(|start| start + offset),
+                     bugline })
+    }
+
+    /// Check that no label is defined twice, and that label 0 is never used.
+    fn validate_labels(program: &Program) -> Res<()> {
+        let mut seen = HashSet::new();
+        for stmt in &program.stmts {
+            if stmt.props.label > 0 && !seen.insert(stmt.props.label) {
+                return Err(IE182.new(None, stmt.props.onthewayto));
+            }
+            let uses_zero = match stmt.body {
+                StmtBody::DoNext(n) |
+                StmtBody::ComeFrom(ComeFrom::Label(n)) |
+                StmtBody::NextFrom(ComeFrom::Label(n)) => n == 0,
+                StmtBody::Abstain(_, ref items) |
+                StmtBody::Reinstate(ref items) => items.contains(&Abstain::Label(0)),
+                _ => false,
+            };
+            if uses_zero {
+                return Err(IE197.new(None, stmt.props.onthewayto));
+            }
+        }
+        Ok(())
     }
 
     pub fn parse(&mut self) -> Res<Vec<Stmt>> {
@@ -152,8 +266,10 @@ impl<'p> Parser<'p> {
         // parse statement inititiator
         if self.take(Rule::DO) {
             props.srcline = self.tokens.lineno();
+            props.srccol = self.tokens.colno();
         } else if self.take(Rule::PLEASEDO) {
             props.srcline = self.tokens.lineno();
+            props.srccol = self.tokens.colno();
             props.polite = true;
         } else {
             return Err(self.invalid());
@@ -193,6 +309,12 @@ impl<'p> Parser<'p> {
             } else {
                 Ok(StmtBody::ComeFrom(ComeFrom::Expr(self.parse_expr()?)))
             }
+        } else if self.take(Rule::NEXTFROM) {
+            if let Some(lbl) = self.parse_label_maybe()? {
+                Ok(StmtBody::NextFrom(ComeFrom::Label(lbl)))
+            } else {
+                Ok(StmtBody::NextFrom(ComeFrom::Gerund(self.parse_gerund()?)))
+            }
         } else if self.take(Rule::RESUME) {
             Ok(StmtBody::Resume(self.parse_expr()?))
         } else if self.take(Rule::FORGET) {
@@ -226,6 +348,10 @@ impl<'p> Parser<'p> {
     fn parse_label_maybe(&mut self) -> ParseRes<Option<ast::Label>> {
         if self.take(Rule::WAX) {
             let lbl = self.req_number(u16::MAX, &IE197)?;
+            if lbl == 0 {
+                // would be indistinguishable from "no label"
+                return Err(DecodeError::Hard(IE197.new(None, self.tokens.lineno())));
+            }
             self.req(Rule::WANE)?;
             Ok(Some(lbl))
         } else {
@@ -298,22 +424,12 @@ impl<'p> Parser<'p> {
         Ok(res)
     }
 
-    /// Parse a list of variables (with subscripts) or consts separated by +.
+    /// Parse a list of expressions (usually variables or consts) separated by +.
     fn parse_readlist(&mut self) -> ParseRes<Vec<Expr>> {
-        let mut res = Vec::new();
-        if self.take(Rule::MESH) {
-            let val = self.req_number(u16::MAX, &IE017)?;
-            res.push(Expr::Num(VType::I16, val as u32));
-        } else {
-            res.push(Expr::Var(self.parse_var(true)?));
-        }
+        // any expression can be read out, not only variables and constants
+        let mut res = vec![self.parse_expr()?];
         while self.take(Rule::INTERSECTION) {
-            if self.take(Rule::MESH) {
-                let val = self.req_number(u16::MAX, &IE017)?;
-                res.push(Expr::Num(VType::I16, val as u32));
-            } else {
-                res.push(Expr::Var(self.parse_var(true)?));
-            }
+            res.push(self.parse_expr()?);
         }
         Ok(res)
     }
@@ -390,6 +506,8 @@ impl<'p> Parser<'p> {
     fn parse_gerund(&mut self) -> ParseRes<Abstain> {
         if self.take(Rule::CALCULATING) {
             Ok(Abstain::Calc)
+        } else if self.take(Rule::NEXTINGFROM) {
+            Ok(Abstain::NextFrom)
         } else if self.take(Rule::NEXTING) {
             Ok(Abstain::Next)
         } else if self.take(Rule::RESUMING) {
@@ -534,7 +652,7 @@ impl<'p> Parser<'p> {
 
     /// Add the syslib to `stmts` if necessary.
     fn add_stdlibs(&self, mut stmts: Vec<Stmt>,
-                   added_syslib: &mut bool, added_floatlib: &mut bool) -> Vec<Stmt> {
+                   added_syslib: &mut bool, floatlib_start: &mut Option<usize>) -> Vec<Stmt> {
         let mut need_syslib = 0;
         let mut need_floatlib = 0;
         for stmt in &stmts {
@@ -567,152 +685,110 @@ impl<'p> Parser<'p> {
         if need_floatlib == 1 {
             let mut p = Parser::new(syslib::FLOATLIB_CODE, last_lineno, false);
             let mut floatlib_stmts = p.parse().expect("E2.0000000001 FLOATLIB BROKEN");
+            *floatlib_start = Some(stmts.len());
             stmts.append(&mut floatlib_stmts);
-            *added_floatlib = true;
         }
         stmts
     }
 
-    /// Walk all references to variables, and call a visitor function for each.
-    fn walk_vars<F>(&self, stmt: &mut Stmt, mut visitor: F)
-        where F: FnMut(&mut Var) -> ()
-    {
-        let visitor = &mut visitor;
-
-        fn walk_var<F>(var: &mut Var, visitor: &mut F)
-            where F: FnMut(&mut Var) -> ()
-        {
-            visitor(var);
-            match *var {
-                Var::A16(_, ref mut es) |
-                Var::A32(_, ref mut es) => {
-                    for e in es {
-                        walk_expr(e, visitor);
-                    }
-                }
-                Var::I16(_) |
-                Var::I32(_) => { }
-            }
-        }
-
-        fn walk_expr<F>(expr: &mut Expr, visitor: &mut F)
-            where F: FnMut(&mut Var) -> ()
-        {
-            match *expr {
-                Expr::Var(ref mut v) => walk_var(v, visitor),
-                Expr::And(_, ref mut e) |
-                Expr::Or(_, ref mut e) |
-                Expr::Xor(_, ref mut e) |
-                Expr::RsNot(ref mut e) => walk_expr(e, visitor),
-                Expr::Mingle(ref mut e, ref mut e2) |
-                Expr::Select(_, ref mut e, ref mut e2) |
-                Expr::RsAnd(ref mut e, ref mut e2) |
-                Expr::RsOr(ref mut e, ref mut e2) |
-                Expr::RsXor(ref mut e, ref mut e2) |
-                Expr::RsRshift(ref mut e, ref mut e2) |
-                Expr::RsLshift(ref mut e, ref mut e2) |
-                // Expr::RsEqual(ref mut e, ref mut e2) |
-                Expr::RsNotEqual(ref mut e, ref mut e2) |
-                Expr::RsPlus(ref mut e, ref mut e2) |
-                Expr::RsMinus(ref mut e, ref mut e2) => {
-                    walk_expr(e, visitor);
-                    walk_expr(e2, visitor);
-                }
-                Expr::Num(_, _) => { }
-            }
-        }
-
-        match stmt.body {
-            StmtBody::Calc(ref mut v, ref mut e) => {
-                walk_var(v, visitor);
-                walk_expr(e, visitor);
-            }
-            StmtBody::Dim(ref mut v, ref mut es) => {
-                walk_var(v, visitor);
-                for e in es {
-                    walk_expr(e, visitor);
-                }
-            }
-            StmtBody::Resume(ref mut e) |
-            StmtBody::Forget(ref mut e) => {
-                walk_expr(e, visitor);
-            }
-            StmtBody::Abstain(ref mut maybe_e, _) => {
-                for e in maybe_e.iter_mut() {
-                    walk_expr(e, visitor);
-                }
-            }
-            StmtBody::ComeFrom(ref mut spec) => {
-                if let ComeFrom::Expr(ref mut e) = *spec {
-                    walk_expr(e, visitor);
-                }
-            }
-            StmtBody::Ignore(ref mut vs) |
-            StmtBody::Remember(ref mut vs) |
-            StmtBody::Stash(ref mut vs) |
-            StmtBody::Retrieve(ref mut vs) |
-            StmtBody::WriteIn(ref mut vs) => {
-                for v in vs {
-                    walk_var(v, visitor);
-                }
-            }
-            StmtBody::ReadOut(ref mut es) => {
-                for e in es {
-                    walk_expr(e, visitor);
-                }
-            }
-            _ => { }
-        }
-    }
-
     /// Collect all used variable numbers and renumber them.
     fn collect_vars(&self, vars: &mut Vars, stmt: &mut Stmt) {
-        self.walk_vars(stmt, |var| {
+        EachVar(|var: &mut Var| {
             let key = var.unique();
             if let HEntry::Vacant(v) = vars.map.entry(key) {
                 let idx = key.0 as usize;
                 v.insert(vars.counts[idx]);
                 vars.counts[idx] += 1;
             }
-        });
+        }).visit_stmt(stmt);
     }
 
     /// Apply variable renumbering.
     fn rename_vars(&self, vars: &Vars, stmt: &mut Stmt) {
-        self.walk_vars(stmt, |var| {
+        EachVar(|var: &mut Var| {
             let key = var.unique();
             var.rename(vars.map[&key]);
-        });
+        }).visit_stmt(stmt);
+    }
+
+    /// Map all come-froms to the logical lines they are coming from, and
+    /// assign them to the statements.  Returns true if computed COME FROMs
+    /// are used, which can only be resolved at runtime.
+    fn resolve_comefroms(stmts: &mut [Stmt], labels: &BTreeMap<ast::Label, ast::LogLine>,
+                         stmt_types: &[Abstain]) -> Res<bool> {
+        let mut comefroms: HashMap<usize, u16> = HashMap::new();
+        let mut uses_complex_comefrom = false;
+        for (i, stmt) in stmts.iter().enumerate() {
+            if let StmtBody::ComeFrom(ref spec) | StmtBody::NextFrom(ref spec) = stmt.body {
+                match *spec {
+                    ComeFrom::Label(n) => {
+                        match labels.get(&n) {
+                            None => return Err(IE444.new(None, stmt.props.onthewayto)),
+                            Some(j) => {
+                                if comefroms.contains_key(&(*j as usize)) {
+                                    return Err(IE555.new(None, stmt.props.onthewayto));
+                                }
+                                comefroms.insert(*j as usize, i as u16);
+                            }
+                        }
+                    }
+                    ComeFrom::Gerund(ref g) => {
+                        for (j, stype) in stmt_types.iter().enumerate() {
+                            if *g == *stype {
+                                if comefroms.contains_key(&j) {
+                                    return Err(IE555.new(None, stmt.props.onthewayto));
+                                }
+                                comefroms.insert(j, i as u16);
+                            }
+                        }
+                    }
+                    ComeFrom::Expr(_) => {
+                        uses_complex_comefrom = true;
+                    }
+                }
+            }
+        }
+        for (i, stmt) in stmts.iter_mut().enumerate() {
+            stmt.comefrom = comefroms.remove(&i);
+        }
+        Ok(uses_complex_comefrom)
     }
 
     /// Do whatever needs to be done after parsing is complete.
     fn post_process(&self, stmts: Vec<Stmt>) -> Res<Program> {
         let mut added_syslib = false;
-        let mut added_floatlib = false;
-        let mut stmts = self.add_stdlibs(stmts, &mut added_syslib, &mut added_floatlib);
+        let mut floatlib_start = None;
+        let mut stmts = if self.stdlibs {
+            self.add_stdlibs(stmts, &mut added_syslib, &mut floatlib_start)
+        } else {
+            stmts
+        };
         let nstmts = stmts.len();
-        let srclines = stmts.iter().map(|s| s.props.srcline).collect::<Vec<_>>();
+        let srclines = stmts.iter().map(|s| (s.props.srcline, s.props.srccol))
+                                   .collect::<Vec<_>>();
         // here we:
         // - determine the "abstain" type of each statement
         // - add "way to" info for the next srcline
+        // - number the statements sharing a source line
         // - create a map of all labels to logical lines
         // - count polite statements
         // - set the correct "on the way to" line for error statements
         // - collect variables for renaming
         let mut npolite = 0;
+        let mut on_line = 0;
         let mut stmt_types = Vec::new();
         let mut labels = BTreeMap::new();
-        let mut comefroms: HashMap<usize, u16> = HashMap::new();
         let mut vars = Vars { counts: vec![0, 0, 0, 0], map: HashMap::new() };
         for (i, mut stmt) in stmts.iter_mut().enumerate() {
             stmt_types.push(stmt.stype());
-            stmt.props.onthewayto =
-                if i < nstmts - 1 { srclines[i + 1] } else { srclines[i] };
+            let (wayline, waycol) = if i < nstmts - 1 { srclines[i + 1] } else { srclines[i] };
+            stmt.props.onthewayto = wayline;
+            stmt.props.onthewaycol = waycol;
+            on_line = if i > 0 && srclines[i - 1].0 == srclines[i].0 { on_line + 1 } else { 1 };
+            stmt.props.stmt_on_line = on_line;
             if stmt.props.label > 0 {
-                if labels.contains_key(&stmt.props.label) {
-                    return Err(IE182.new(None, stmt.props.onthewayto));
-                }
-                labels.insert(stmt.props.label, i as u16);
+                // duplicates are reported by validate_labels
+                labels.entry(stmt.props.label).or_insert(i as u16);
             }
             if stmt.props.polite {
                 npolite += 1;
@@ -731,40 +807,10 @@ impl<'p> Parser<'p> {
             }
         }
         // here we:
-        // - create a map of all come-froms to logical lines
         // - apply new variable names
         // - make sure abstain labels exist
         // - make sure TRY AGAIN is last in the file
-        let mut uses_complex_comefrom = false;
         for (i, mut stmt) in stmts.iter_mut().enumerate() {
-            if let StmtBody::ComeFrom(ref spec) = stmt.body {
-                match *spec {
-                    ComeFrom::Label(n) => {
-                        match labels.get(&n) {
-                            None => return Err(IE444.new(None, stmt.props.onthewayto)),
-                            Some(j) => {
-                                if comefroms.contains_key(&(*j as usize)) {
-                                    return Err(IE555.new(None, stmt.props.onthewayto));
-                                }
-                                comefroms.insert(*j as usize, i as u16);
-                            }
-                        }
-                    }
-                    ComeFrom::Gerund(ref g) => {
-                        for (j, stype) in stmt_types.iter().enumerate() {
-                            if *g == *stype {
-                                if comefroms.contains_key(&j) {
-                                    return Err(IE555.new(None, stmt.props.onthewayto));
-                                }
-                                comefroms.insert(j, i as u16);
-                            }
-                        }
-                    }
-                    ComeFrom::Expr(_) => {
-                        uses_complex_comefrom = true;
-                    }
-                }
-            }
             self.rename_vars(&vars, &mut stmt);
             if let StmtBody::Abstain(_, ref v) = stmt.body {
                 if let Abstain::Label(n) = v[0] {
@@ -780,11 +826,7 @@ impl<'p> Parser<'p> {
                 }
             }
         }
-        // here we:
-        // - assign comefroms to statements
-        for (i, mut stmt) in stmts.iter_mut().enumerate() {
-            stmt.comefrom = comefroms.remove(&i);
-        }
+        let uses_complex_comefrom = Parser::resolve_comefroms(&mut stmts, &labels, &stmt_types)?;
         // select a line for the compiler bug
         let mut rng = rand::thread_rng();
         let bugline = if self.allow_bug && rng.gen_range(0, 10) == 0 {
@@ -792,18 +834,27 @@ impl<'p> Parser<'p> {
         } else {
             stmts.len()  // can never be reached
         } as u16;
-        // collect variable counts
-        let var_info = (vec![VarInfo::new(); vars.counts[0]],
-                        vec![VarInfo::new(); vars.counts[1]],
-                        vec![VarInfo::new(); vars.counts[2]],
-                        vec![VarInfo::new(); vars.counts[3]]);
+        // collect variable counts and remember the original numbers
+        let mut var_info = (vec![VarInfo::new(0); vars.counts[0]],
+                            vec![VarInfo::new(0); vars.counts[1]],
+                            vec![VarInfo::new(0); vars.counts[2]],
+                            vec![VarInfo::new(0); vars.counts[3]]);
+        for (&(vtype, orig), &new) in &vars.map {
+            match vtype {
+                0 => var_info.0[new].orig = orig,
+                1 => var_info.1[new].orig = orig,
+                2 => var_info.2[new].orig = orig,
+                _ => var_info.3[new].orig = orig,
+            }
+        }
         Ok(Program { stmts,
                      labels,
                      stmt_types,
                      var_info,
                      uses_complex_comefrom,
                      added_syslib,
-                     added_floatlib,
+                     added_floatlib: floatlib_start.is_some(),
+                     floatlib_start,
                      bugline })
     }
 }
@@ -814,3 +865,15 @@ struct Vars {
     counts: Vec<usize>,
     map: HashMap<(u8, usize), usize>,
 }
+
+/// Calls a function for each reference to a variable in the statements visited,
+/// including those in subscripts.
+struct EachVar<F>(F);
+
+impl<F: FnMut(&mut Var)> ExprVisitor for EachVar<F> {
+    fn visit_var(&mut self, var: &mut Var) {
+        (self.0)(var);
+    }
+}
+
+impl<F: FnMut(&mut Var)> StmtVisitor for EachVar<F> { }
@@ -0,0 +1,249 @@
+// -------------------------------------------------------------------------------------------------
+// Rick, a Rust intercal compiler.  Save your souls!
+//
+// Copyright (c) 2015-2017 Georg Brandl
+//
+// This program is free software; you can redistribute it and/or modify it under the terms of the
+// GNU General Public License as published by the Free Software Foundation; either version 2 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without
+// even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with this program;
+// if not, write to the Free Software Foundation, Inc., 675 Mass Ave, Cambridge, MA 02139, USA.
+// -------------------------------------------------------------------------------------------------
+
+//! Wires up the parser, optimizer and interpreter, for embedding Rick.
+//!
+//! A `Pipeline` holds all the settings of the single stages, so that source
+//! code can be turned into a `Program` and run with one call each.
+
+use std::io::Cursor;
+use std::time::Duration;
+
+use crate::ast::{Program, Var};
+use crate::err::{Res, IE990};
+use crate::eval::{Eval, InputMode, RomanSep, Termination};
+use crate::opt::{Optimizer, OptStats};
+use crate::parse::Parser;
+
+
+/// Settings for compiling and running programs.
+pub struct Pipeline {
+    allow_bug: bool,
+    optimize: bool,
+    allow_const_out: bool,
+    opt_budget: Option<Duration>,
+    input: Vec<u8>,
+    seed: Option<u64>,
+    roman_sep: RomanSep,
+    roman_width: usize,
+    input_mode: InputMode,
+    preserve_on_redim: bool,
+    truncate_mingle: bool,
+    fall_off_ok: bool,
+    max_elems: Option<usize>,
+    max_output: Option<usize>,
+    max_output_lines: Option<usize>,
+    max_stmts: Option<usize>,
+    read_only: Vec<Var>,
+    fixed_input: Option<Vec<u8>>,
+}
+
+/// What a program did when run through a `Pipeline`.
+#[allow(dead_code)]  // for embedding
+#[derive(Debug)]
+pub struct RunResult {
+    /// Everything the program wrote.
+    pub output: Vec<u8>,
+    /// Number of statements, including those abstained from or skipped.
+    pub stmts: usize,
+    /// Number of statements actually executed.
+    pub executed: usize,
+    /// Highest depth the NEXT stack reached.
+    pub max_jump_depth: usize,
+    /// How the program ended.
+    pub termination: Termination,
+}
+
+#[allow(dead_code)]  // for embedding
+impl Pipeline {
+    /// Construct a pipeline with the defaults of the command line: no compiler
+    /// bug, no optimization, empty input and a fixed random seed.
+    pub fn new() -> Pipeline {
+        Pipeline {
+            allow_bug: false,
+            optimize: false,
+            allow_const_out: true,
+            opt_budget: None,
+            input: Vec::new(),
+            seed: Some(0),
+            roman_sep: RomanSep::Newline,
+            roman_width: 0,
+            input_mode: InputMode::Number,
+            preserve_on_redim: false,
+            truncate_mingle: false,
+            fall_off_ok: false,
+            max_elems: None,
+            max_output: None,
+            max_output_lines: None,
+            max_stmts: None,
+            read_only: Vec::new(),
+            fixed_input: None,
+        }
+    }
+
+    /// Allow the random compiler bug E774.
+    pub fn set_allow_bug(&mut self, allow: bool) {
+        self.allow_bug = allow;
+    }
+
+    /// Select whether to optimize, and whether programs with constant output
+    /// may be reduced to that output.
+    pub fn set_optimize(&mut self, optimize: bool, allow_const_out: bool) {
+        self.optimize = optimize;
+        self.allow_const_out = allow_const_out;
+    }
+
+    /// Specialize programs for a fixed input when optimizing, see
+    /// `Optimizer::set_fixed_input`.  This reduces the program to constant
+    /// output, so optimizing fails (IE990) if `set_optimize` does not allow
+    /// that, like `-F` with `--specialize` on the command line.
+    pub fn set_fixed_input(&mut self, input: &[u8]) {
+        self.fixed_input = Some(input.to_vec());
+    }
+
+    /// Limit the time spent in the optimizer, see `Optimizer::set_budget`.
+    pub fn set_opt_budget(&mut self, budget: Duration) {
+        self.opt_budget = Some(budget);
+    }
+
+    /// Set the input programs read.
+    pub fn set_input(&mut self, input: &[u8]) {
+        self.input = input.to_vec();
+    }
+
+    /// Set the random seed, or `None` to seed randomly.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    /// See `Eval::set_roman_sep`.
+    pub fn set_roman_sep(&mut self, sep: RomanSep) {
+        self.roman_sep = sep;
+    }
+
+    /// See `Eval::set_roman_width`.
+    pub fn set_roman_width(&mut self, width: usize) {
+        self.roman_width = width;
+    }
+
+    /// See `Eval::set_input_mode`.
+    pub fn set_input_mode(&mut self, mode: InputMode) {
+        self.input_mode = mode;
+    }
+
+    /// See `Eval::set_preserve_on_redim`.
+    pub fn set_preserve_on_redim(&mut self, preserve: bool) {
+        self.preserve_on_redim = preserve;
+    }
+
+    /// See `Eval::set_truncate_mingle`.
+    pub fn set_truncate_mingle(&mut self, truncate: bool) {
+        self.truncate_mingle = truncate;
+    }
+
+    /// See `Eval::set_fall_off_ok`.
+    pub fn set_fall_off_ok(&mut self, ok: bool) {
+        self.fall_off_ok = ok;
+    }
+
+    /// See `Eval::set_max_elems`.
+    pub fn set_max_elems(&mut self, max: usize) {
+        self.max_elems = Some(max);
+    }
+
+    /// See `Eval::set_max_output`.
+    pub fn set_max_output(&mut self, max: usize) {
+        self.max_output = Some(max);
+    }
+
+    /// See `Eval::set_max_output_lines`.
+    pub fn set_max_output_lines(&mut self, max: usize) {
+        self.max_output_lines = Some(max);
+    }
+
+    /// See `Eval::set_max_stmts`.
+    pub fn set_max_stmts(&mut self, max: usize) {
+        self.max_stmts = Some(max);
+    }
+
+    /// See `Eval::set_read_only`.
+    pub fn set_read_only(&mut self, vars: &[Var]) {
+        self.read_only = vars.to_vec();
+    }
+
+    /// Parse source code, and optimize it if selected.
+    pub fn compile(&self, code: &str) -> Res<Program> {
+        let program = Parser::new(code, 1, self.allow_bug).get_program()?;
+        if !self.optimize {
+            return Ok(program);
+        }
+        Ok(self.optimize_program(program)?.0)
+    }
+
+    /// Optimize a parsed program with the configured settings, whether or not
+    /// optimization is selected for `compile`, and also report what the
+    /// optimizer found out.  Nothing is run, except by the constant output
+    /// pass if it is allowed.
+    pub fn optimize_program(&self, program: Program) -> Res<(Program, OptStats)> {
+        if self.fixed_input.is_some() && !self.allow_const_out {
+            return IE990.err();
+        }
+        let mut optimizer = Optimizer::new(program, self.allow_const_out);
+        if let Some(budget) = self.opt_budget {
+            optimizer.set_budget(budget);
+        }
+        if let Some(ref input) = self.fixed_input {
+            optimizer.set_fixed_input(input.clone());
+        }
+        Ok(optimizer.optimize())
+    }
+
+    /// Run a program on the configured input.
+    pub fn run(&self, program: &Program) -> Res<RunResult> {
+        let mut stdin = Cursor::new(&self.input[..]);
+        let mut output = Vec::new();
+        let (outcome, executed, max_jump_depth) = {
+            let mut eval = Eval::new(program, &mut stdin, &mut output, false, self.seed.is_none());
+            if let Some(seed) = self.seed {
+                eval.set_seed(seed);
+            }
+            eval.set_roman_sep(self.roman_sep);
+            eval.set_roman_width(self.roman_width);
+            eval.set_input_mode(self.input_mode);
+            eval.set_preserve_on_redim(self.preserve_on_redim);
+            eval.set_truncate_mingle(self.truncate_mingle);
+            eval.set_fall_off_ok(self.fall_off_ok);
+            if let Some(max) = self.max_elems {
+                eval.set_max_elems(max);
+            }
+            if let Some(max) = self.max_output {
+                eval.set_max_output(max);
+            }
+            if let Some(max) = self.max_output_lines {
+                eval.set_max_output_lines(max);
+            }
+            if let Some(max) = self.max_stmts {
+                eval.set_max_stmts(max);
+            }
+            eval.set_read_only(&self.read_only);
+            let outcome = eval.eval()?;
+            (outcome, eval.executed_count(), eval.max_jump_depth())
+        };
+        Ok(RunResult { output, stmts: outcome.stmts, executed, max_jump_depth,
+                       termination: outcome.termination })
+    }
+}
@@ -30,17 +30,18 @@
 /// * Basic read/write of numbers and bytes
 /// * all the INTERCAL operators (mingle, select, unary and, unary or, unary xor)
 
+use std::convert::TryFrom;
 use std::fmt::{Debug, Display, Error, Formatter};
 use std::fs::File;
-use std::io::{Read, Write, stdin};
+use std::io::{BufRead, Read, Write};
 use std::{u16, u32};
 
 use crate::err::{Res, IE240, IE241, IE252, IE436, IE533, IE562, IE579, IE621, IE632};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Array<T> {
-    pub dims: Vec<usize>,
-    pub elems: Vec<T>,
+    dims: Vec<usize>,
+    elems: Vec<T>,
 }
 
 impl<T: Clone + Default> Array<T> {
@@ -50,9 +51,43 @@ impl<T: Clone + Default> Array<T> {
         Array { dims, elems: vec![value; total] }
     }
 
+    /// Iterate over all elements, in storage order (first subscript varies fastest).
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.elems.iter().cloned()
+    }
+
+    #[allow(dead_code)]  // for inspecting state after a run
+    pub fn dims(&self) -> &[usize] {
+        &self.dims
+    }
+
     pub fn empty() -> Array<T> {
         Array { dims: vec![], elems: vec![] }
     }
+
+    /// Make an array with new dimensions, keeping the elements whose subscripts
+    /// are still valid if the number of dimensions stays the same.
+    fn resized(&self, dims: Vec<usize>) -> Array<T> {
+        let mut new = Array::new(dims);
+        if new.dims.len() != self.dims.len() {
+            return new;
+        }
+        'elems: for (ix, elem) in self.elems.iter().enumerate() {
+            // convert the index back into subscripts, and into the new index
+            let (mut rest, mut new_ix, mut new_prev_dim) = (ix, 0, 1);
+            for (&dim, &new_dim) in self.dims.iter().zip(&new.dims) {
+                let sub = rest % dim;
+                if sub >= new_dim {
+                    continue 'elems;
+                }
+                rest /= dim;
+                new_ix += sub * new_prev_dim;
+                new_prev_dim *= new_dim;
+            }
+            new.elems[new_ix] = elem.clone();
+        }
+        new
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -62,6 +97,16 @@ pub struct Bind<T> {
     pub rw: bool,
 }
 
+/// Bindings compare equal if their current values do; the STASH stack and
+/// whether the variable is IGNOREd are not compared.
+impl<T: PartialEq> PartialEq for Bind<T> {
+    fn eq(&self, other: &Bind<T>) -> bool {
+        self.val == other.val
+    }
+}
+
+impl<T: Eq> Eq for Bind<T> { }
+
 impl<T: Clone> Bind<T> {
     pub fn new(t: T) -> Bind<T> {
         Bind { val: t, stack: Vec::new(), rw: true }
@@ -161,12 +206,27 @@ impl<T: LikeU16 + Default> Bind<Array<T>> {
         Ok(ix as usize)
     }
 
+    #[allow(dead_code)]  // only used in compiled code
     pub fn dimension(&mut self, dims: Vec<usize>, line: usize) -> Res<()> {
-        if dims.iter().product::<usize>() == 0 {
+        self.dimension_capped(dims, false, usize::MAX, line)
+    }
+
+    /// Like `dimension`, but refuse arrays with more than `max_elems` elements,
+    /// and optionally keep the elements whose subscripts stay valid.
+    pub fn dimension_capped(&mut self, dims: Vec<usize>, preserve: bool, max_elems: usize,
+                            line: usize) -> Res<()> {
+        // an empty dimension list would give a single-element array with no way to
+        // subscript it, so treat it like a zero dimension
+        if dims.is_empty() || dims.contains(&0) {
             return IE240.err_with(None, line);
         }
+        // the element count must not overflow, even on 32-bit targets
+        match dims.iter().try_fold(1usize, |acc, &dim| acc.checked_mul(dim)) {
+            Some(total) if total <= max_elems => (),
+            _ => return IE241.err_with(None, line),
+        }
         if self.rw {
-            self.val = Array::new(dims);
+            self.val = if preserve { self.val.resized(dims) } else { Array::new(dims) };
         }
         Ok(())
     }
@@ -176,26 +236,18 @@ impl<T: LikeU16 + Default> Bind<Array<T>> {
             // only dimension-1 arrays can be output
             return IE241.err_with(None, line);
         }
-        let mut res = Vec::with_capacity(self.val.elems.len());
-        for val in &self.val.elems {
-            let byte = ((*state as i16 - val.to_u16() as i16) as u16 % 256) as u8;
-            let mut c = byte;
-            *state = byte as u8;
-            c = (c & 0x0f) << 4 | (c & 0xf0) >> 4;
-            c = (c & 0x33) << 2 | (c & 0xcc) >> 2;
-            c = (c & 0x55) << 1 | (c & 0xaa) >> 1;
-            res.push(c);
-        }
+        let res = self.val.elems.iter().map(|val| tape_out(state, val.to_u16()))
+                                       .collect::<Vec<_>>();
         write_bytes(w, &res, line)
     }
 
-    pub fn writein(&mut self, state: &mut u8, line: usize) -> Res<()> {
+    pub fn writein(&mut self, r: &mut dyn BufRead, state: &mut u8, line: usize) -> Res<()> {
         if self.val.dims.len() != 1 {
             // only dimension-1 arrays can be input
             return IE241.err_with(None, line);
         }
         for place in &mut self.val.elems {
-            let byte = read_byte();
+            let byte = read_byte(r);
             let c = if byte == 256 {
                 *state = 0;
                 256
@@ -244,7 +296,23 @@ pub fn get_random_seed() -> u32 {
     seed
 }
 
+/// One step of array output on the Turing tape: get the byte to write for an
+/// element, given the tape state (the bit-reversed last byte), and update it.
+pub fn tape_out(state: &mut u8, val: u16) -> u8 {
+    let byte = ((*state as i16 - val as i16) as u16 % 256) as u8;
+    let mut c = byte;
+    *state = byte;
+    c = (c & 0x0f) << 4 | (c & 0xf0) >> 4;
+    c = (c & 0x33) << 2 | (c & 0xcc) >> 2;
+    c = (c & 0x55) << 1 | (c & 0xaa) >> 1;
+    c
+}
+
 /// Check statement execution chance (false -> skip).
+///
+/// This only uses integer operations: the drawn number is reduced to 0-99 and
+/// compared against the percentage directly.  Keep it that way, since changing
+/// how the number is drawn also changes what a run with a fixed seed does.
 pub fn check_chance(chance: u8, state: &mut u32) -> bool {
     if chance == 100 {
         true
@@ -258,6 +326,10 @@ pub fn check_chance(chance: u8, state: &mut u32) -> bool {
 }
 
 /// Pop "n" jumps from the jump stack and return the last one.
+///
+/// RESUME is `strict`: it fails for #0 (IE621) and for more entries than the
+/// stack holds (IE632).  FORGET just does nothing for #0, and clears the stack
+/// if it would pop more entries than there are.
 pub fn pop_jumps<T>(jumps: &mut Vec<T>, n: u32, strict: bool, line: usize) -> Res<Option<T>> {
     if n == 0 {
         if strict {
@@ -309,8 +381,14 @@ const ROMAN_DIGIT_TBL: [[(char, char); 4]; 10] = [
     [(' ', 'c'), (' ', 'c'), (' ', 'd'), (' ', 'm')],
     [(' ', 'm'), ('_', 'i'), ('_', 'v'), ('_', 'x')]];
 
-/// Convert a number into Roman numeral representation.
-pub fn to_roman(mut val: u32) -> String {
+/// Largest number that has a Roman numeral representation: the numerals only
+/// go up to ten decimal places, which is plenty for 32 bits but not for more.
+pub const MAX_ROMAN: u64 = 9_999_999_999;
+
+/// Convert a number into Roman numeral representation.  It must not be larger
+/// than `MAX_ROMAN`.
+pub fn to_roman<T: Into<u64>>(val: T) -> String {
+    let mut val = val.into();
     if val == 0 {
         // zero is just a lone overbar
         return "_\n\n".into();
@@ -332,6 +410,51 @@ pub fn to_roman(mut val: u32) -> String {
             l2.into_iter().rev().collect::<String>())
 }
 
+/// Get the value of a single roman digit, with or without overbar.
+fn roman_digit_value(overbar: char, digit: char) -> Option<u64> {
+    for (place, digits) in ROMAN_DIGIT_TBL.iter().enumerate() {
+        let unit = 10u64.pow(place as u32);
+        for (idx, &entry) in digits.iter().enumerate() {
+            if entry == (overbar, digit) {
+                return Some(match idx { 0 | 1 => unit, 2 => 5 * unit, _ => 10 * unit });
+            }
+        }
+    }
+    None
+}
+
+/// Convert a number from the two-line Roman numeral representation that
+/// `to_roman` produces (without the newlines).
+pub fn from_roman(overbars: &str, digits: &str, line: usize) -> Res<u32> {
+    if digits.is_empty() && overbars == "_" {
+        return Ok(0);
+    }
+    if digits.is_empty() || overbars.chars().count() != digits.chars().count() {
+        return IE579.err_with(Some(digits), line);
+    }
+    let mut values = Vec::new();
+    for (o, d) in overbars.chars().zip(digits.chars()) {
+        match roman_digit_value(o, d) {
+            Some(v) => values.push(v),
+            None => return IE579.err_with(Some(&d.to_string()), line),
+        }
+    }
+    let mut res = 0;
+    for (i, &v) in values.iter().enumerate() {
+        // a smaller digit before a larger one is subtracted
+        if values.get(i + 1).is_some_and(|&next| next > v) {
+            res -= v as i64;
+        } else {
+            res += v as i64;
+        }
+    }
+    if res < 0 || res > (u32::MAX as i64) {
+        IE533.err_with(None, line)
+    } else {
+        Ok(res as u32)
+    }
+}
+
 const ENGLISH_DIGITS: [(&str, u64); 12] = [
     ("ZERO",  0),
     ("OH",    0),
@@ -346,31 +469,128 @@ const ENGLISH_DIGITS: [(&str, u64); 12] = [
     ("NINE",  9),
     ("NINER", 9)];
 
-/// Convert a number represented as digits spelled out in English.
-pub fn from_english(v: &str, line: usize) -> Res<u32> {
-    let mut res = 0;
-    for word in v.split_whitespace() {
-        match ENGLISH_DIGITS.iter().find(|entry| entry.0 == word) {
-            Some(&(_, digit)) => res = 10*res + digit,
-            None => return IE579.err_with(Some(word), line)
+/// Convert a number represented as digits spelled out in English.  Numbers
+/// that do not fit into `T` give an error.
+pub fn from_english<T: TryFrom<u128>>(v: &str, line: usize) -> Res<T> {
+    from_words(v, false, line)
+}
+
+/// Like `from_english`, but also accept plain decimal digits, alone or mixed
+/// with spelled out ones: "ONE 2 THREE" and "123" are both 123.
+pub fn from_english_or_digits<T: TryFrom<u128>>(v: &str, line: usize) -> Res<T> {
+    from_words(v, true, line)
+}
+
+/// Like `from_english`, but a leading word HEX or OCTAL selects that radix
+/// for the following digit words, of which there must be at least one.  Hex
+/// digits above NINE are the single letters A to F.  Digit words too large for
+/// the radix are rejected.
+pub fn from_english_radix<T: TryFrom<u128>>(v: &str, line: usize) -> Res<T> {
+    let mut words = v.split_whitespace().peekable();
+    let radix = match words.peek() {
+        Some(&"HEX") => 16,
+        Some(&"OCTAL") => 8,
+        _ => 10,
+    };
+    if radix != 10 {
+        let prefix = words.next();
+        // the radix word alone is not a number
+        if words.peek().is_none() {
+            return IE579.err_with(prefix, line);
         }
     }
-    if res > (u32::MAX as u64) {
-        IE533.err_with(None, line)
-    } else {
-        Ok(res as u32)
+    from_radix_words(words, radix, false, line)?.map_or_else(|| IE533.err_with(None, line), Ok)
+}
+
+/// Like `from_english`, but numbers too large for 32 bits become the largest
+/// 32-bit number instead of giving an error, for input whose exact value does
+/// not matter.  Unknown words are still an error.
+pub fn from_english_saturating(v: &str, line: usize) -> Res<u32> {
+    Ok(from_radix_words(v.split_whitespace(), 10, false, line)?.unwrap_or(u32::MAX))
+}
+
+fn from_words<T: TryFrom<u128>>(v: &str, allow_digits: bool, line: usize) -> Res<T> {
+    from_radix_words(v.split_whitespace(), 10, allow_digits, line)?
+        .map_or_else(|| IE533.err_with(None, line), Ok)
+}
+
+/// Convert digit words in the given radix; None if the number does not fit
+/// into `T`.  The remaining words are still checked then.
+fn from_radix_words<'a, T: TryFrom<u128>>(words: impl Iterator<Item = &'a str>, radix: u128,
+                                          allow_digits: bool, line: usize) -> Res<Option<T>> {
+    // u128 has room for one more digit than the widest T we use
+    let mut res = Some(0u128);
+    let mut push = |digit: u128| {
+        // once too large, stay so; leading zeros are fine however many there are
+        res = res.map(|res| radix*res + digit).filter(|&res| T::try_from(res).is_ok());
+    };
+    for word in words {
+        let digit = match ENGLISH_DIGITS.iter().find(|entry| entry.0 == word) {
+            Some(&(_, digit)) => Some(digit as u128),
+            None if radix == 16 && word.len() == 1 && ("A"..="F").contains(&word) => {
+                Some((word.as_bytes()[0] - b'A' + 10) as u128)
+            }
+            None => None,
+        };
+        match digit {
+            Some(digit) if digit < radix => push(digit),
+            None if allow_digits && word.bytes().all(|b| b.is_ascii_digit()) => {
+                for b in word.bytes() {
+                    push((b - b'0') as u128);
+                }
+            }
+            _ => return IE579.err_with(Some(word), line)
+        }
     }
+    Ok(res.and_then(|res| T::try_from(res).ok()))
 }
 
 /// Output a number in Roman format.
-pub fn write_number(w: &mut dyn Write, val: u32, line: usize) -> Res<()> {
+pub fn write_number<T: Into<u64>>(w: &mut dyn Write, val: T, line: usize) -> Res<()> {
+    let val = val.into();
+    if val > MAX_ROMAN {
+        return IE533.err_with(None, line);
+    }
     if write!(w, "{}", to_roman(val)).is_err() {
         return IE252.err_with(None, line);
     }
     Ok(())
 }
 
-/// Output a byte.
+/// Append a number in Roman format to the two lines of a row of numbers
+/// put side by side, both padded to at least `min_width` characters.
+pub fn push_roman(bars: &mut String, digits: &mut String, val: u32, min_width: usize) {
+    let roman = to_roman(val);
+    let mut lines = roman.split('\n');
+    let l1 = lines.next().unwrap_or("");
+    let l2 = lines.next().unwrap_or("");
+    // zero has an overbar but no digits, so pad to keep the lines aligned
+    let width = l1.len().max(l2.len()).max(min_width);
+    bars.push_str(&format!("{:1$}", l1, width));
+    digits.push_str(&format!("{:1$}", l2, width));
+}
+
+/// Output several numbers in Roman format side by side, separated by a space
+/// and sharing the overbar line.  Each number is padded to `min_width`.
+pub fn write_roman_row(w: &mut dyn Write, vals: &[u32], min_width: usize,
+                       line: usize) -> Res<()> {
+    let mut bars = String::new();
+    let mut digits = String::new();
+    for (i, &val) in vals.iter().enumerate() {
+        if i > 0 {
+            bars.push(' ');
+            digits.push(' ');
+        }
+        push_roman(&mut bars, &mut digits, val, min_width);
+    }
+    if writeln!(w, "{}\n{}", bars, digits).is_err() {
+        return IE252.err_with(None, line);
+    }
+    Ok(())
+}
+
+/// Output raw bytes.  They are written as they are, without going through
+/// `char`, so that bytes 128-255 stay single bytes instead of becoming UTF-8.
 pub fn write_bytes(w: &mut dyn Write, val: &[u8], line: usize) -> Res<()> {
     if w.write_all(val).is_err() {
         return IE252.err_with(None, line);
@@ -379,18 +599,58 @@ pub fn write_bytes(w: &mut dyn Write, val: &[u8], line: usize) -> Res<()> {
 }
 
 /// Read a number in spelled out English format.
-pub fn read_number(line: usize) -> Res<u32> {
+pub fn read_number<T: TryFrom<u128>>(r: &mut dyn BufRead, line: usize) -> Res<T> {
     let mut buf = String::new();
-    match stdin().read_line(&mut buf) {
+    match r.read_line(&mut buf) {
         Ok(n) if n > 1 => from_english(&buf, line),
         _              => IE562.err_with(None, line)
     }
 }
 
-/// Read a byte from stdin.
-pub fn read_byte() -> u16 {
+/// Read a number in spelled out English format, optionally hex or octal.
+pub fn read_number_radix<T: TryFrom<u128>>(r: &mut dyn BufRead, line: usize) -> Res<T> {
+    let mut buf = String::new();
+    match r.read_line(&mut buf) {
+        Ok(n) if n > 1 => from_english_radix(&buf, line),
+        _              => IE562.err_with(None, line)
+    }
+}
+
+/// Read a number in spelled out English format, saturating on overflow.
+pub fn read_number_saturating(r: &mut dyn BufRead, line: usize) -> Res<u32> {
+    let mut buf = String::new();
+    match r.read_line(&mut buf) {
+        Ok(n) if n > 1 => from_english_saturating(&buf, line),
+        _              => IE562.err_with(None, line)
+    }
+}
+
+/// Read a number in spelled out English format, or with plain digits.
+pub fn read_number_or_digits<T: TryFrom<u128>>(r: &mut dyn BufRead, line: usize) -> Res<T> {
+    let mut buf = String::new();
+    match r.read_line(&mut buf) {
+        Ok(n) if n > 1 => from_english_or_digits(&buf, line),
+        _              => IE562.err_with(None, line)
+    }
+}
+
+/// Read a number in Roman format, given on two lines like on output.
+pub fn read_roman(r: &mut dyn BufRead, line: usize) -> Res<u32> {
+    let mut overbars = String::new();
+    let mut digits = String::new();
+    match (r.read_line(&mut overbars), r.read_line(&mut digits)) {
+        (Ok(n), Ok(m)) if n > 0 && m > 0 => {
+            from_roman(overbars.trim_end_matches(&['\n', '\r'][..]),
+                       digits.trim_end_matches(&['\n', '\r'][..]), line)
+        }
+        _ => IE562.err_with(None, line)
+    }
+}
+
+/// Read a byte from the input stream.
+pub fn read_byte(r: &mut dyn BufRead) -> u16 {
     let mut buf = [0u8; 1];
-    match stdin().read(&mut buf) {
+    match r.read(&mut buf) {
         Ok(1) => buf[0] as u16,
         _     => 256      // EOF is defined to be 256
     }
@@ -405,8 +665,10 @@ pub fn check_ovf(v: u32, line: usize) -> Res<u32> {
     }
 }
 
-/// Implements the Mingle operator.
-pub fn mingle(mut v: u32, mut w: u32) -> u32 {
+/// Implements the Mingle operator.  Both operands must fit in 16 bits.
+pub fn mingle(v: u32, w: u32, line: usize) -> Res<u32> {
+    let mut v = check_ovf(v, line)?;
+    let mut w = check_ovf(w, line)?;
     v = ((v & 0x0000_ff00) << 8) | (v & 0x0000_00ff);
     v = ((v & 0x00f0_00f0) << 4) | (v & 0x000f_000f);
     v = ((v & 0x0c0c_0c0c) << 2) | (v & 0x0303_0303);
@@ -415,11 +677,17 @@ pub fn mingle(mut v: u32, mut w: u32) -> u32 {
     w = ((w & 0x00f0_00f0) << 4) | (w & 0x000f_000f);
     w = ((w & 0x0c0c_0c0c) << 2) | (w & 0x0303_0303);
     w = ((w & 0x2222_2222) << 1) | (w & 0x1111_1111);
-    (v << 1) | w
+    Ok((v << 1) | w)
 }
 
 /// Implements the Select operator.
 pub fn select(mut v: u32, mut w: u32) -> u32 {
+    // a contiguous run of ones in the mask is just a shift and a mask
+    // (this never matches for w == 0, which has 32 zeros but 64 leading/trailing)
+    let (lz, tz) = (w.leading_zeros(), w.trailing_zeros());
+    if w.count_zeros() == lz + tz {
+        return (v >> tz) & (u32::MAX >> (lz + tz));
+    }
     let mut i = 1;
     let mut t = 0;
     while w > 0 {
@@ -435,6 +703,34 @@ pub fn select(mut v: u32, mut w: u32) -> u32 {
     t
 }
 
+/// Which operand of a mingle ends up in the odd (higher) bit of each pair.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MingleOrder {
+    /// The first operand, as in INTERCAL-72 (the default).
+    FirstHigh,
+    /// The second operand.
+    FirstLow,
+}
+
+/// Mingle with a selectable bit order.
+pub fn mingle_with(v: u32, w: u32, order: MingleOrder, line: usize) -> Res<u32> {
+    match order {
+        MingleOrder::FirstHigh => mingle(v, w, line),
+        MingleOrder::FirstLow => mingle(w, v, line),
+    }
+}
+
+/// The inverse of `mingle_with`: select both operands back out of a mingled
+/// value.
+#[allow(dead_code)]  // for tooling
+pub fn unmingle_with(z: u32, order: MingleOrder) -> (u32, u32) {
+    let (high, low) = (select(z, 0xAAAA_AAAA), select(z, 0x5555_5555));
+    match order {
+        MingleOrder::FirstHigh => (high, low),
+        MingleOrder::FirstLow => (low, high),
+    }
+}
+
 pub fn and_16(v: u32) -> u32 {
     let mut w = v >> 1;
     if v & 1 > 0 {
@@ -497,3 +793,8 @@ impl LikeU16 for u32 {
     fn from_u16(x: u16) -> u32 { x as u32 }
     fn to_u16(self) -> u16 { self as u16 }
 }
+
+impl LikeU16 for u64 {
+    fn from_u16(x: u16) -> u64 { x as u64 }
+    fn to_u16(self) -> u16 { self as u16 }
+}
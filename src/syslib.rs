@@ -20,6 +20,12 @@
 /// The syslib is appended to INTERCAL programs when they jump to a label
 /// in the range 1000 to 1999 (but don't define any such label).
 /// The same goes for floatlib with the range 5000 to 5999.
+///
+/// The random number routines (1900 and 1910 in the syslib, 5400 in the floatlib)
+/// are plain INTERCAL using %-qualified statements, so they draw from the same
+/// generator as all other execution chances: the state of the `Eval` (seedable
+/// with `Eval::run`) or of the compiled program.  With -R, the seed is fixed and
+/// their results are reproducible.
 
 // This is the syslib.i from C-INTERCAL 0.30.
 
@@ -0,0 +1,1164 @@
+// -------------------------------------------------------------------------------------------------
+// Rick, a Rust intercal compiler.  Save your souls!
+//
+// Copyright (c) 2015-2017 Georg Brandl
+//
+// This program is free software; you can redistribute it and/or modify it under the terms of the
+// GNU General Public License as published by the Free Software Foundation; either version 2 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without
+// even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with this program;
+// if not, write to the Free Software Foundation, Inc., 675 Mass Ave, Cambridge, MA 02139, USA.
+// -------------------------------------------------------------------------------------------------
+
+//! Tests of the APIs for embedders and tools, which the `.i`/`.chk` suite in
+//! `code/tests` cannot reach: it only runs programs through the command line,
+//! without extra options.
+//!
+//! Run with `cargo test`.  Programs are given inline and run through
+//! `Eval::run` or an `Eval` writing into a buffer; numbers in the expected
+//! output are written in Roman numerals, overbar line first.
+
+use std::collections::HashSet;
+use std::io::{self, BufRead, Cursor, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::ast::{Abstain, Expr, ExprVisitor, Program, StmtBody, StmtVisitor, Var, VType};
+use crate::err;
+use crate::eval::{Eval, EvalHooks, InputMode, OutputItem, RomanSep, ScriptedInput, Termination,
+                  Val};
+use crate::opt::{self, Optimizer, Pass};
+use crate::parse::Parser;
+use crate::pipeline::Pipeline;
+use crate::stdops::{from_roman, mingle_with, read_number, select, to_roman, unmingle_with,
+                    write_number, Array, Bind, MingleOrder, MAX_ROMAN};
+use crate::syslib;
+
+fn parse(code: &str) -> Program {
+    Parser::new(code, 1, false).get_program().expect("program does not parse")
+}
+
+fn output(program: &Program) -> String {
+    let (output, _) = Eval::run(program, &[], Some(0)).expect("program fails");
+    String::from_utf8(output).unwrap()
+}
+
+/// Run `f` on an evaluator for the program, reading `input`, and return its
+/// result together with the output.
+fn with_eval<T>(program: &Program, input: &[u8], f: impl FnOnce(&mut Eval) -> T) -> (T, String) {
+    let mut stdin = Cursor::new(input);
+    let mut stdout = Vec::new();
+    let res = f(&mut Eval::new(program, &mut stdin, &mut stdout, false, false));
+    (res, String::from_utf8(stdout).unwrap())
+}
+
+const ADD_CODE: &str = "DO .1 <- #2\nDO .2 <- #3\nPLEASE (1009) NEXT\nDO READ OUT .3\nDO GIVE UP\n";
+
+#[test]
+fn concat_shared_syslib() {
+    let syslib = parse(syslib::SYSLIB_CODE);
+    // with the syslib appended while parsing, the labels clash
+    assert!(Parser::concat(parse(ADD_CODE), syslib.clone()).is_err());
+    let mut parser = Parser::new(ADD_CODE, 1, false);
+    parser.set_stdlibs(false);
+    let program = Parser::concat(parser.get_program().unwrap(), syslib).unwrap();
+    assert_eq!(output(&program), " \nV\n");
+}
+
+#[test]
+fn diverging_pass_is_named() {
+    fn break_output(mut program: Program) -> Program {
+        for stmt in &mut program.stmts {
+            if let StmtBody::ReadOut(ref mut exprs) = stmt.body {
+                exprs[0] = Expr::Num(VType::I16, 7);
+            }
+        }
+        program
+    }
+    let optimizer = Optimizer::new(parse("DO .1 <- #1\nPLEASE READ OUT .1\nDO GIVE UP\n"), false);
+    let mut passes = optimizer.passes();
+    assert_eq!(optimizer.find_diverging_pass(&[], &passes), None);
+    let broken: Pass = ("broken", break_output);
+    passes.insert(2, broken);
+    assert_eq!(optimizer.find_diverging_pass(&[], &passes), Some("broken"));
+}
+
+#[test]
+fn replayed_trace_gives_same_run() {
+    let program = parse("DO .1 <- #1\nDO %50 READ OUT #1\nPLEASE %50 READ OUT #2\n\
+                         DO %50 READ OUT #3\nPLEASE %50 READ OUT #4\nDO GIVE UP\n");
+    let record = |seed| with_eval(&program, &[], |eval| {
+        eval.set_seed(seed);
+        eval.record_trace();
+        eval.eval().unwrap();
+        eval.trace().unwrap().to_vec()
+    });
+    let (trace, out) = record(1);
+    // find a seed that, by itself, takes other chances
+    let seed = (2..100).find(|&seed| record(seed).1 != out).unwrap();
+    let (replayed, replay_out) = with_eval(&program, &[], |eval| {
+        eval.set_seed(seed);
+        eval.record_trace();
+        eval.set_replay(&trace);
+        eval.eval().unwrap();
+        eval.trace().unwrap().to_vec()
+    });
+    assert_eq!(replay_out, out);
+    assert_eq!(replayed, trace);
+}
+
+const WATCH_CODE: &str = "DO .1 <- #1\nDO ,1 <- #3\nDO ,1 SUB #1 <- #5\nPLEASE .2 <- #2\n\
+                          DO ,1 SUB #2 <- #7\nPLEASE .1 <- #3\nDO GIVE UP\n";
+
+#[test]
+fn watch_scalar() {
+    let program = parse(WATCH_CODE);
+    let (stops, _) = with_eval(&program, &[], |eval| {
+        (0..3).map(|_| eval.run_to_watch(&Var::I16(1)).unwrap()).collect::<Vec<_>>()
+    });
+    assert_eq!(stops, [Some(0), Some(5), None]);
+}
+
+#[test]
+fn watch_array_element() {
+    let program = parse(WATCH_CODE);
+    let elem = Var::A16(1, vec![Expr::Num(VType::I16, 2)]);
+    let (stops, _) = with_eval(&program, &[], |eval| {
+        (0..3).map(|_| eval.run_to_watch(&elem).unwrap()).collect::<Vec<_>>()
+    });
+    // dimensioning makes the element exist; assigning another one is no change
+    assert_eq!(stops, [Some(1), Some(4), None]);
+}
+
+/// Hooks that record the assignments and jumps as text.
+#[derive(Default)]
+struct Recorder(Vec<String>);
+
+impl EvalHooks for Recorder {
+    fn on_assign(&mut self, var: &Var, val: &Val) {
+        self.0.push(format!("{} = {}", var, val));
+    }
+    fn on_jump(&mut self, from: usize, to: usize) {
+        self.0.push(format!("jump {} -> {}", from, to));
+    }
+    fn on_comefrom(&mut self, from: usize, to: usize) {
+        self.0.push(format!("come from {} -> {}", from, to));
+    }
+}
+
+#[test]
+fn hooks_see_assignments_and_jumps() {
+    let program = parse("DO .1 <- #1\nDO (10) NEXT\n(5) PLEASE .2 <- #2\nDO GIVE UP\n\
+                         (10) DO .3 <- #3\nPLEASE RESUME #1\nDO COME FROM (5)\nDO GIVE UP\n");
+    let mut recorder = Recorder::default();
+    let mut stdin = Cursor::new(&[][..]);
+    let mut stdout = Vec::new();
+    {
+        let mut eval = Eval::new(&program, &mut stdin, &mut stdout, false, false);
+        eval.set_hooks(&mut recorder);
+        eval.eval().unwrap();
+    }
+    assert_eq!(recorder.0, [".1 = 1:I16", "jump 1 -> 4", ".3 = 3:I16", "jump 5 -> 2",
+                            ".2 = 2:I16", "come from 2 -> 6"]);
+}
+
+#[test]
+fn read_only_var_refuses_calc() {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_read_only(&[Var::I16(2)]);
+    let program = pipeline.compile("DO .1 <- #1\nPLEASE .2 <- #2\nDO GIVE UP\n").unwrap();
+    let err = pipeline.run(&program).unwrap_err();
+    assert!(err.to_string().starts_with("ICL212I"));
+    // other variables are not affected
+    pipeline.set_read_only(&[Var::I16(3)]);
+    assert!(pipeline.run(&program).is_ok());
+}
+
+#[test]
+fn preset_var_is_read_out() {
+    let program = parse("PLEASE READ OUT .1\nDO READ OUT ,1 SUB #2\nDO GIVE UP\n");
+    let (res, out) = with_eval(&program, &[], |eval| {
+        eval.preset(&Var::I16(1), Val::I16(4))?;
+        eval.preset_dims(&Var::A16(1, vec![]), &[2])?;
+        eval.preset(&Var::A16(1, vec![Expr::Num(VType::I16, 2)]), Val::I16(3))?;
+        // not used by the program
+        eval.preset(&Var::I16(2), Val::I16(7))?;
+        eval.eval()
+    });
+    res.unwrap();
+    assert_eq!(out, "  \nIV\n   \nIII\n");
+}
+
+#[test]
+fn eval_expression_sees_preset() {
+    let program = parse("PLEASE READ OUT .1\nDO GIVE UP\n");
+    let (val, _) = with_eval(&program, &[], |eval| {
+        eval.preset(&Var::I16(1), Val::I16(3)).unwrap();
+        // the program's only spot variable is number 0 in its statements
+        let expr = Expr::Mingle(Box::new(Expr::Var(Var::I16(0))),
+                                Box::new(Expr::Num(VType::I16, 0)));
+        eval.eval_expression(&expr)
+    });
+    assert_eq!(val.unwrap(), Val::I32(0b1010));
+}
+
+#[test]
+fn output_is_recorded_as_numbers_and_bytes() {
+    let program = parse("DO .1 <- #7\nPLEASE READ OUT .1\nDO READ OUT #65535\n\
+                         DO ,1 <- #1\nPLEASE DO ,1 SUB #1 <- #126\nDO READ OUT ,1\n\
+                         DO READ OUT #0\nDO GIVE UP\n");
+    let (log, _) = with_eval(&program, &[], |eval| {
+        eval.record_output();
+        eval.eval().unwrap();
+        eval.output_log().unwrap().to_vec()
+    });
+    // 126 is the bit-reversed difference to the previous byte 0, giving 'A'
+    assert_eq!(log, [OutputItem::Number(7), OutputItem::Number(65535), OutputItem::Byte(b'A'),
+                     OutputItem::Number(0)]);
+}
+
+#[test]
+fn error_names_statement_on_line() {
+    let code = "DO .1 <- #1\nDO .1 <- #2 PLEASE RESUME #0 DO .2 <- #3\nDO GIVE UP\n";
+    let err = Eval::run(&parse(code), &[], Some(0)).unwrap_err();
+    assert!(err.display_with_source(code).contains("\tin the 2nd statement on line 2\n"));
+}
+
+#[test]
+fn echo_program_is_specialized() {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_optimize(true, true);
+    pipeline.set_fixed_input(b"FOUR\n");
+    let program = pipeline.compile("DO WRITE IN .1\nPLEASE READ OUT .1\nDO GIVE UP\n").unwrap();
+    let bodies: Vec<_> = program.stmts.iter().map(|stmt| &stmt.body).collect();
+    assert_eq!(bodies, [&StmtBody::Print(b"  \nIV\n".to_vec()), &StmtBody::GiveUp]);
+}
+
+#[test]
+fn evals_run_on_scoped_threads() {
+    let program = parse("DO WRITE IN .1\nPLEASE READ OUT .1\nDO GIVE UP\n");
+    let (mut in1, mut in2) = (Cursor::new(&b"ONE\n"[..]), Cursor::new(&b"TWO\n"[..]));
+    let (mut out1, mut out2) = (Vec::new(), Vec::new());
+    let mut eval1 = Eval::new(&program, &mut in1, &mut out1, false, false);
+    let mut eval2 = Eval::new(&program, &mut in2, &mut out2, false, false);
+    thread::scope(|scope| {
+        let t1 = scope.spawn(move || eval1.eval());
+        let t2 = scope.spawn(move || eval2.eval());
+        t1.join().unwrap().unwrap();
+        t2.join().unwrap().unwrap();
+    });
+    assert_eq!((&out1[..], &out2[..]), (&b" \nI\n"[..], &b"  \nII\n"[..]));
+}
+
+#[test]
+fn scripted_input_feeds_write_in() {
+    let program = parse("DO WRITE IN .1\nPLEASE READ OUT .1\nDO WRITE IN .2\nDO READ OUT .2\n\
+                         DO WRITE IN .3\nPLEASE GIVE UP\n");
+    let mut stdin = ScriptedInput::new();
+    stdin.push_line("TWO");
+    stdin.push_line("FIVE");
+    let mut stdout = Vec::new();
+    // the input runs out at the third WRITE IN
+    let err = Eval::new(&program, &mut stdin, &mut stdout, false, false).eval().unwrap_err();
+    assert!(err.to_string().starts_with("ICL562I"));
+    assert_eq!(stdout, b"  \nII\n \nV\n");
+
+    let program = parse("DO WRITE IN .1\nPLEASE READ OUT .1\nDO WRITE IN .2\nDO READ OUT .2\n\
+                         DO GIVE UP\n");
+    let mut stdin = ScriptedInput::new();
+    stdin.push_bytes("\x04");
+    let mut stdout = Vec::new();
+    let mut eval = Eval::new(&program, &mut stdin, &mut stdout, false, false);
+    eval.set_input_mode(InputMode::Byte);
+    eval.eval().unwrap();
+    // a byte read at EOF gives 256
+    assert_eq!(stdout, b"  \nIV\n     \nCCLVI\n");
+}
+
+#[test]
+fn error_is_looked_up_by_code() {
+    assert_eq!(err::by_code(129), Some(&err::IE129));
+    assert_eq!(err::by_code(130), None);
+    let codes: Vec<String> = err::all_codes().iter()
+        .map(|e| e.new(None, 0).to_string()[3..6].to_string()).collect();
+    assert!(codes.windows(2).all(|w| w[0] < w[1]));
+    assert!(codes.contains(&"129".to_string()));
+}
+
+#[test]
+fn dumped_array_is_column_major() {
+    let program = parse("DO ,1 <- #2 BY #3\nDO ,1 SUB #2 #1 <- #5\nPLEASE ,1 SUB #1 #2 <- #7\n\
+                         DO GIVE UP\n");
+    let (vals, _) = with_eval(&program, &[], |eval| {
+        eval.eval().unwrap();
+        assert_eq!(eval.dump_array(&Var::A16(2, vec![])), None);
+        eval.dump_array(&Var::A16(1, vec![]))
+    });
+    let expected = [0, 5, 7, 0, 0, 0].iter().map(|&v| Val::I16(v)).collect::<Vec<_>>();
+    assert_eq!(vals, Some(expected));
+}
+
+#[test]
+fn roman_numerals_round_trip() {
+    for &n in &[0u32, 1, 4, 9, 14, 3999, 4000, 65535, 1_000_000, 1_234_567, u32::MAX] {
+        let roman = to_roman(n);
+        let mut lines = roman.split('\n');
+        let (overbars, digits) = (lines.next().unwrap(), lines.next().unwrap());
+        assert_eq!(from_roman(overbars, digits, 0).unwrap(), n, "{:?}", roman);
+    }
+    assert_eq!(to_roman(1_000_000u32), "_\nM\n");
+}
+
+fn var(n: usize) -> Box<Expr> {
+    Box::new(Expr::Var(Var::I32(n)))
+}
+
+fn num(n: u32) -> Box<Expr> {
+    Box::new(Expr::Num(VType::I32, n))
+}
+
+#[test]
+fn peephole_select_is_shift_and_mask() {
+    let select = |mask| Expr::Select(VType::I32, var(0), num(mask));
+    assert_eq!(opt::rewrite_select_shift_mask(&select(0xFF)),
+               Some(Expr::RsAnd(var(0), num(0xFF))));
+    assert_eq!(opt::rewrite_select_shift_mask(&select(0xFFFF_0000)),
+               Some(Expr::RsRshift(var(0), num(16))));
+    assert_eq!(opt::rewrite_select_shift_mask(&select(0xF0)),
+               Some(Expr::RsAnd(Box::new(Expr::RsRshift(var(0), num(4))), num(0xF))));
+    // inside zeros need a real select
+    assert_eq!(opt::rewrite_select_shift_mask(&select(0x101)), None);
+}
+
+#[test]
+fn peephole_shifts_are_merged() {
+    let shifts = |a, b| Expr::RsLshift(Box::new(Expr::RsLshift(var(0), num(a))), num(b));
+    assert_eq!(opt::rewrite_shift_merge(&shifts(3, 4)), Some(Expr::RsLshift(var(0), num(7))));
+    assert_eq!(opt::rewrite_shift_merge(&shifts(16, 16)), Some(Expr::Num(VType::I32, 0)));
+    // shifts in different directions are left alone
+    let mixed = Expr::RsRshift(Box::new(Expr::RsLshift(var(0), num(3))), num(4));
+    assert_eq!(opt::rewrite_shift_merge(&mixed), None);
+}
+
+#[test]
+fn peephole_same_operands() {
+    assert_eq!(opt::rewrite_same_operands(&Expr::RsOr(var(0), var(0))), Some(*var(0)));
+    assert_eq!(opt::rewrite_same_operands(&Expr::RsXor(var(0), var(0))),
+               Some(Expr::Num(VType::I32, 0)));
+    assert_eq!(opt::rewrite_same_operands(&Expr::RsAnd(var(0), var(1))), None);
+}
+
+#[test]
+fn peepholes_run_on_program() {
+    // selecting the low bits becomes a mask
+    let program = parse("DO :1 <- #1\nDO :2 <- :1 ~ #65535\nPLEASE READ OUT :2\nDO GIVE UP\n");
+    let program = Optimizer::opt_expressions(program);
+    match program.stmts[1].body {
+        StmtBody::Calc(_, ref expr) => assert_eq!(*expr, Expr::RsAnd(var(0), num(0xFFFF))),
+        ref body => panic!("unexpected statement {:?}", body),
+    }
+}
+
+/// Output that can be looked at while the evaluator still writes into it.
+struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Input that remembers what had been output when it was first read.
+struct PromptedInput {
+    input: Cursor<Vec<u8>>,
+    output: Arc<Mutex<Vec<u8>>>,
+    prompt: Option<Vec<u8>>,
+}
+
+impl Read for PromptedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill_buf()?;
+        self.input.read(buf)
+    }
+}
+
+impl BufRead for PromptedInput {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.prompt.is_none() {
+            self.prompt = Some(self.output.lock().unwrap().clone());
+        }
+        self.input.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.input.consume(amt)
+    }
+}
+
+#[test]
+fn side_by_side_prompt_comes_before_input() {
+    let program = parse("DO .1 <- #1\nDO READ OUT .1 + #3\nPLEASE WRITE IN .2\nDO READ OUT .2\n\
+                         DO GIVE UP\n");
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let mut stdin = PromptedInput { input: Cursor::new(b"TWO\n".to_vec()),
+                                    output: output.clone(), prompt: None };
+    let mut stdout = SharedOutput(output.clone());
+    let mut eval = Eval::new(&program, &mut stdin, &mut stdout, false, false);
+    eval.set_roman_sep(RomanSep::Space);
+    eval.eval().unwrap();
+    assert_eq!(stdin.prompt.unwrap(), b"     \nI III\n");
+    assert_eq!(&output.lock().unwrap()[..], &b"     \nI III\n  \nII\n"[..]);
+}
+
+#[test]
+fn dead_floatlib_is_stripped() {
+    let program = parse(include_str!("../code/tests/floatlibdead.i"));
+    assert!(program.added_floatlib);
+    assert_eq!(program.floatlib_start, Some(5));
+    // with more statements after it, the floatlib is left alone
+    let longer = Parser::concat(program.clone(), parse("DO .9 <- #1\n")).unwrap();
+    assert_eq!(longer.floatlib_start, None);
+    let longer_len = longer.stmts.len();
+    assert_eq!(Optimizer::opt_strip_floatlib(longer).stmts.len(), longer_len);
+    let program = Optimizer::opt_strip_floatlib(program);
+    // only the program's own statements are left
+    assert_eq!(program.stmts.len(), 5);
+    assert!(program.labels.keys().all(|&label| label < 5000));
+    assert!(!program.added_floatlib);
+    assert_eq!(output(&program), "   \nIII\n");
+
+    // a reachable floatlib routine keeps it
+    let program = Optimizer::opt_strip_floatlib(parse("DO .1 <- #3\nPLEASE (5000) NEXT\n"));
+    assert!(program.added_floatlib && program.labels.contains_key(&5000));
+}
+
+#[test]
+fn native_shifts_by_32_or_more_give_zero() {
+    let program = parse("DO GIVE UP\n");
+    let (vals, _) = with_eval(&program, &[], |eval| {
+        [31, 32, 40].iter().map(|&amount| {
+            let left = Expr::RsLshift(num(0xFFFF_FFFF), num(amount));
+            let right = Expr::RsRshift(num(0xFFFF_FFFF), num(amount));
+            (eval.eval_expression(&left).unwrap(), eval.eval_expression(&right).unwrap())
+        }).collect::<Vec<_>>()
+    });
+    assert_eq!(vals, [(Val::I32(0x8000_0000), Val::I32(1)), (Val::I32(0), Val::I32(0)),
+                      (Val::I32(0), Val::I32(0))]);
+}
+
+#[test]
+fn read_only_error_names_subscripts_as_in_source() {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_read_only(&[Var::A16(3, vec![])]);
+    let program = pipeline.compile("DO .5 <- #1\nDO .7 <- #1\nDO ,3 <- #2\n\
+                                    PLEASE ,3 SUB .7 <- :1\nDO GIVE UP\n").unwrap();
+    let err = pipeline.run(&program).unwrap_err();
+    assert!(err.to_string().contains(",3 SUB .7"), "{}", err.to_string());
+}
+
+#[test]
+fn shared_program_runs_on_spawned_threads() {
+    let program = Arc::new(parse("DO WRITE IN .1\nPLEASE READ OUT .1\nDO GIVE UP\n"));
+    let threads: Vec<_> = [&b"ONE\n"[..], &b"TWO\n"[..]].iter().map(|&input| {
+        let program = Arc::clone(&program);
+        thread::spawn(move || Eval::run(&program, input, Some(0)).unwrap().0)
+    }).collect();
+    let outputs: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+    assert_eq!(outputs, [b" \nI\n".to_vec(), b"  \nII\n".to_vec()]);
+}
+
+#[test]
+fn overflowing_input_saturates_if_asked() {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_input(b"FOUR TWO NINE FOUR NINE SIX SEVEN TWO NINE SIX\n");
+    let program = pipeline.compile("DO WRITE IN :1\nPLEASE READ OUT :1\nDO GIVE UP\n").unwrap();
+    // 4294967296 is one above the largest 32-bit number
+    let err = pipeline.run(&program).unwrap_err();
+    assert!(err.to_string().starts_with("ICL533I"));
+    pipeline.set_input_mode(InputMode::Saturating);
+    let result = pipeline.run(&program).unwrap();
+    assert_eq!(result.output, to_roman(u32::MAX).into_bytes());
+}
+
+#[test]
+fn used_vars_lists_all_kinds() {
+    let program = parse("DO ,2 <- #3\nDO ,2 SUB .1 <- :3\nPLEASE DO ;4 <- #2\n\
+                         DO ;4 SUB .5 <- .1\nPLEASE STASH .6\nDO GIVE UP\n");
+    let used: Vec<_> = program.used_vars().iter().map(|v| program.source_var(v)).collect();
+    // first uses, including those in subscripts and STASH lists
+    assert_eq!(used, [Var::A16(2, vec![]), Var::I16(1), Var::I32(3), Var::A32(4, vec![]),
+                      Var::I16(5), Var::I16(6)]);
+}
+
+/// Records each expression and variable visited, numbered as in the source.
+struct NodeRecorder<'a>(&'a Program, Vec<String>);
+
+impl<'a> ExprVisitor for NodeRecorder<'a> {
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        self.1.push(self.0.source_expr(expr).to_string());
+    }
+
+    fn visit_var(&mut self, var: &mut Var) {
+        self.1.push(format!("var {}", self.0.source_var(var)));
+    }
+}
+
+impl<'a> StmtVisitor for NodeRecorder<'a> { }
+
+#[test]
+fn visitor_reaches_every_node() {
+    let program = parse("DO ,1 <- #2 BY .2
+DO ,1 SUB .2 #1 <- :3
+PLEASE ABSTAIN .4 FROM (1)
+\
+                         (1) DO COME FROM .5
+DO STASH .6 + ;7
+DO WRITE IN ,1 SUB .8
+\
+                         PLEASE READ OUT ,1 SUB #1
+DO RESUME .9
+DO FORGET #1
+\
+                         DO IGNORE :10
+PLEASE GIVE UP
+");
+    let mut nodes = NodeRecorder(&program, vec![]);
+    nodes.walk_program(&mut program.clone());
+    // variables come before their subscripts, expressions after their operands
+    assert_eq!(nodes.1, ["var ,1", "#2", "var .2", ".2",
+                         "var ,1 SUB .2 #1", "var .2", ".2", "#1", "var :3", ":3",
+                         "var .4", ".4",
+                         "var .5", ".5",
+                         "var .6", "var ;7",
+                         "var ,1 SUB .8", "var .8", ".8",
+                         "var ,1 SUB #1", "#1", ",1 SUB #1",
+                         "var .9", ".9",
+                         "#1",
+                         "var :10"]);
+}
+
+#[test]
+fn vars_display_with_sigils() {
+    assert_eq!(Var::I16(1).to_string(), ".1");
+    assert_eq!(Var::I32(2).to_string(), ":2");
+    assert_eq!(Var::A16(3, vec![*var(1)]).to_string(), ",3 SUB :1");
+    assert_eq!(Var::A32(4, vec![*var(1), *num(2)]).to_string(), ";4 SUB :1 #2");
+}
+
+#[test]
+fn high_byte_is_written_as_is() {
+    // 237 goes on the tape as 19, which is 200 with its bits reversed
+    let program = parse("DO ,1 <- #1\nDO ,1 SUB #1 <- #237\nPLEASE READ OUT ,1\nDO GIVE UP\n");
+    let (output, _) = Eval::run(&program, &[], Some(0)).unwrap();
+    assert_eq!(output, [200]);
+}
+
+#[test]
+fn select_inverts_mingle_in_both_orders() {
+    for &order in &[MingleOrder::FirstHigh, MingleOrder::FirstLow] {
+        for &(v, w) in &[(0, 0xFFFF), (0x1234, 0xABCD), (0xFFFF, 1)] {
+            let z = mingle_with(v, w, order, 0).unwrap();
+            assert_eq!(unmingle_with(z, order), (v, w));
+        }
+    }
+    // #1 $ #0 is 2 in the default order, and 1 with the first operand low
+    let program = parse("DO :1 <- #1 $ #0\nPLEASE READ OUT :1\nDO GIVE UP\n");
+    assert_eq!(output(&program), "  \nII\n");
+    let (res, output) = with_eval(&program, b"", |eval| {
+        eval.set_mingle_order(MingleOrder::FirstLow);
+        eval.eval()
+    });
+    res.unwrap();
+    assert_eq!(output, " \nI\n");
+}
+
+#[test]
+fn extended_program_keeps_gerund_abstentions() {
+    let first = parse("DO .1 <- #1\nDO ABSTAIN FROM CALCULATING\n");
+    let more = parse("DO READ OUT .1\nDO .1 <- #2\nPLEASE READ OUT .1\n");
+    let whole = Parser::concat(first.clone(), more).unwrap();
+    let mut stdin = Cursor::new(&b""[..]);
+    let mut stdout = Vec::new();
+    {
+        let mut eval = Eval::new(&first, &mut stdin, &mut stdout, false, false);
+        eval.set_fall_off_ok(true);
+        eval.eval().unwrap();
+        eval.extend(&whole);
+        eval.eval().unwrap();
+    }
+    // the appended assignment is abstained from like the first one
+    assert_eq!(String::from_utf8(stdout).unwrap(), " \nI\n \nI\n");
+}
+
+#[test]
+fn runs_report_how_they_ended() {
+    let mut pipeline = Pipeline::new();
+    let program = pipeline.compile("DO .1 <- #1\nPLEASE GIVE UP\n").unwrap();
+    assert_eq!(pipeline.run(&program).unwrap().termination, Termination::GaveUp);
+    let program = pipeline.compile("DO .1 <- #1\nPLEASE READ OUT .1\n").unwrap();
+    pipeline.set_fall_off_ok(true);
+    assert_eq!(pipeline.run(&program).unwrap().termination, Termination::FellOffEnd);
+    let program = pipeline.compile("(1) DO .1 <- #1\nPLEASE (1) NEXT\n").unwrap();
+    pipeline.set_max_stmts(10);
+    let result = pipeline.run(&program).unwrap();
+    assert_eq!((result.termination, result.stmts), (Termination::LimitReached, 10));
+}
+
+#[test]
+fn radix_input_reads_hex_octal_and_decimal() {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_input_mode(InputMode::Radix);
+    let program = pipeline.compile("DO WRITE IN :1\nPLEASE READ OUT :1\nDO GIVE UP\n").unwrap();
+    for &(input, value) in &[(&b"HEX F F\n"[..], 255u32), (b"OCTAL SEVEN SEVEN\n", 63),
+                             (b"TWO FIVE FIVE\n", 255)] {
+        pipeline.set_input(input);
+        assert_eq!(pipeline.run(&program).unwrap().output, to_roman(value).into_bytes());
+    }
+    for &input in &[&b"HEX\n"[..], b"OCTAL\n", b"OCTAL EIGHT\n"] {
+        pipeline.set_input(input);
+        let err = pipeline.run(&program).unwrap_err();
+        assert!(err.to_string().starts_with("ICL579I"), "{}", err.to_string());
+    }
+}
+
+#[test]
+fn listing_diff_shows_rewritten_statements() {
+    let before = parse("DO .1 <- #1 $ #2\nPLEASE READ OUT .1\nDO GIVE UP\n");
+    let after = Optimizer::opt_constant_fold(before.clone());
+    // the listing shows variables as renumbered by the parser
+    assert_eq!(Optimizer::listing_diff(&before, &after),
+               "- #001          DO         .0 <- (#1 $ #2)\n\
+                + #001          DO         .0 <- #6\n\
+                \x20 #002          PLEASE     READ OUT .0\n\
+                \x20 #003          DO         GIVE UP\n");
+}
+
+#[test]
+fn next_targets_are_the_labels_jumped_to() {
+    // (30) resumes past the NEXT from (10), so (20) is never jumped to
+    let program = parse("DO .1 <- #2\nDO (10) NEXT\nPLEASE GIVE UP\n(10) DO (30) NEXT\n\
+                         DO (20) NEXT\n(30) PLEASE RESUME .1\n(20) DO GIVE UP\n");
+    let (targets, output) = with_eval(&program, b"", |eval| {
+        eval.record_next_targets();
+        eval.eval().unwrap();
+        eval.next_targets().cloned()
+    });
+    assert_eq!(output, "");
+    assert_eq!(targets, Some([10, 30].iter().cloned().collect::<HashSet<_>>()));
+}
+
+#[test]
+fn debugger_abstains_and_reinstates_labels() {
+    let program = parse("(1) DO READ OUT #1\nDO GIVE UP\n");
+    let (_, output) = with_eval(&program, b"", |eval| {
+        eval.set_abstain(&Abstain::Label(1), true).unwrap();
+        eval.eval().unwrap()
+    });
+    assert_eq!(output, "");
+    let (_, output) = with_eval(&program, b"", |eval| {
+        eval.set_abstain(&Abstain::Label(1), true).unwrap();
+        eval.set_abstain(&Abstain::Label(1), false).unwrap();
+        eval.eval().unwrap()
+    });
+    assert_eq!(output, " \nI\n");
+    let (res, _) = with_eval(&program, b"", |eval| eval.set_abstain(&Abstain::Label(2), true));
+    assert!(res.unwrap_err().to_string().starts_with("ICL139I"));
+}
+
+#[test]
+fn wide_mingle_operands_are_truncated_if_asked() {
+    let mut pipeline = Pipeline::new();
+    // :2 is 131072, whose lower 16 bits are all zero
+    let program = pipeline.compile("DO :2 <- #256 $ #0\nDO :1 <- :2 $ #1\n\
+                                    PLEASE READ OUT :1\nDO GIVE UP\n").unwrap();
+    let err = pipeline.run(&program).unwrap_err();
+    assert!(err.to_string().starts_with("ICL533I"), "{}", err.to_string());
+    pipeline.set_truncate_mingle(true);
+    assert_eq!(pipeline.run(&program).unwrap().output, b" \nI\n");
+}
+
+#[test]
+fn output_lines_are_capped() {
+    // each number takes two lines
+    let mut pipeline = Pipeline::new();
+    let program = pipeline.compile("DO READ OUT #1\nDO READ OUT #2\nPLEASE GIVE UP\n").unwrap();
+    pipeline.set_max_output_lines(3);
+    let err = pipeline.run(&program).unwrap_err();
+    assert!(err.to_string().starts_with("ICL252I"), "{}", err.to_string());
+    pipeline.set_max_output_lines(4);
+    assert_eq!(pipeline.run(&program).unwrap().output, b" \nI\n  \nII\n");
+}
+
+#[test]
+fn falling_off_the_end_is_fine_if_asked() {
+    let mut pipeline = Pipeline::new();
+    let program = pipeline.compile("DO .1 <- #1\nPLEASE READ OUT .1\n").unwrap();
+    let err = pipeline.run(&program).unwrap_err();
+    assert!(err.to_string().starts_with("ICL633I"), "{}", err.to_string());
+    pipeline.set_fall_off_ok(true);
+    let result = pipeline.run(&program).unwrap();
+    assert_eq!((result.output, result.termination), (b" \nI\n".to_vec(), Termination::FellOffEnd));
+}
+
+#[test]
+fn vals_display_with_their_width() {
+    assert_eq!(Val::I16(42).to_string(), "42:I16");
+    assert_eq!(Val::I32(70000).to_string(), "70000:I32");
+}
+
+#[test]
+fn statements_are_found_by_label() {
+    let program = parse("(10) DO .1 <- #1\nPLEASE GIVE UP\n");
+    let stmt = program.stmt_by_label(10).expect("label 10 is defined");
+    assert_eq!(stmt.body, StmtBody::Calc(Var::I16(0), Expr::Num(VType::I16, 1)));
+    assert!(program.stmt_by_label(20).is_none());
+}
+
+#[test]
+fn arrays_compare_dims_and_elements() {
+    let mut first = Bind::new(Array::<u16>::new(vec![2, 3]));
+    let mut second = Bind::new(Array::<u16>::new(vec![2, 3]));
+    first.set_md(&[2, 1], 7, 0).unwrap();
+    second.set_md(&[2, 1], 7, 0).unwrap();
+    // only the current values count, not the STASH stack
+    second.stash();
+    assert_eq!(first, second);
+    second.set_md(&[1, 3], 1, 0).unwrap();
+    assert_ne!(first, second);
+    // the same number of elements, but other dimensions
+    assert_ne!(Array::<u16>::new(vec![2, 3]), Array::new(vec![3, 2]));
+}
+
+#[test]
+fn wide_arrays_round_trip() {
+    let wide = 5_000_000_000u64;
+    let mut input = Cursor::new(&b"FIVE ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO ZERO\n"[..]);
+    let val: u64 = read_number(&mut input, 0).unwrap();
+    assert_eq!(val, wide);
+    let mut array = Bind::new(Array::<u64>::new(vec![2]));
+    array.set_md(&[1], u64::MAX, 0).unwrap();
+    array.set_md(&[2], val, 0).unwrap();
+    assert_eq!(array.val.iter().collect::<Vec<_>>(), [u64::MAX, wide]);
+    let mut output = Vec::new();
+    write_number(&mut output, array.get_md(&[2], 0).unwrap(), 0).unwrap();
+    assert_eq!(output, to_roman(wide).into_bytes());
+    // past ten decimal places, there is no Roman numeral
+    let err = write_number(&mut output, MAX_ROMAN + 1, 0).unwrap_err();
+    assert!(err.to_string().starts_with("ICL533I"));
+}
+
+#[test]
+fn constant_program_is_optimized_to_its_output() {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_optimize(true, true);
+    let program = parse("DO .1 <- #3\nPLEASE READ OUT .1\nDO GIVE UP\n");
+    let (program, stats) = pipeline.optimize_program(program).unwrap();
+    let bodies: Vec<_> = program.stmts.iter().map(|stmt| &stmt.body).collect();
+    assert_eq!(bodies, [&StmtBody::Print(b"   \nIII\n".to_vec()), &StmtBody::GiveUp]);
+    assert!(!stats.const_out_skipped);
+    assert_eq!(stats.passes_skipped, 0);
+}
+
+#[test]
+fn constant_output_program_is_listed() {
+    let program = parse("DO .1 <- #3\nPLEASE READ OUT .1\nDO GIVE UP\n");
+    let program = Optimizer::opt_const_output(program);
+    // the made up statements have no source line
+    assert_eq!(program.to_string(), "#000          DO         <PRINT 8 BYTES>\n\
+                                     #000          DO         GIVE UP\n");
+}
+
+#[test]
+fn constant_subscripts_are_folded() {
+    let program = parse("DO ,1 <- #4\nDO ,1 SUB #0 $ #2 <- #5\nPLEASE GIVE UP\n");
+    let program = Optimizer::opt_constant_fold(program);
+    // #0 $ #2 is 4
+    let elem = Var::A16(0, vec![Expr::Num(VType::I16, 4)]);
+    assert_eq!(program.stmts[1].body, StmtBody::Calc(elem, Expr::Num(VType::I16, 5)));
+}
+
+#[test]
+fn pipeline_compiles_and_runs_source() {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_optimize(true, false);
+    pipeline.set_input(b"SIX\n");
+    let program = pipeline.compile("DO WRITE IN .1\nDO .2 <- #3\nPLEASE (1009) NEXT\n\
+                                    DO READ OUT .3\nDO GIVE UP\n").unwrap();
+    let result = pipeline.run(&program).unwrap();
+    assert_eq!(result.output, b"  \nIX\n");
+    assert_eq!(result.termination, Termination::GaveUp);
+}
+
+#[test]
+fn redimensioning_keeps_values_if_asked() {
+    let mut pipeline = Pipeline::new();
+    let program = pipeline.compile("DO ,1 <- #2\nDO ,1 SUB #2 <- #7\nDO ,1 <- #3\n\
+                                    PLEASE READ OUT ,1 SUB #2\nDO READ OUT ,1 SUB #3\n\
+                                    DO ,1 <- #3 BY #1\nPLEASE READ OUT ,1 SUB #2 #1\n\
+                                    DO GIVE UP\n").unwrap();
+    let zero = to_roman(0u32);
+    let reset = format!("{}{}{}", zero, zero, zero);
+    assert_eq!(pipeline.run(&program).unwrap().output, reset.into_bytes());
+    // growing keeps the old value, another number of dimensions does not
+    pipeline.set_preserve_on_redim(true);
+    let kept = format!("{}{}{}", to_roman(7u32), zero, zero);
+    assert_eq!(pipeline.run(&program).unwrap().output, kept.into_bytes());
+}
+
+#[test]
+fn analysis_runs_without_budget() {
+    let program = parse("DO .1 <- #1\nDO STASH .2\nDO .2 <- #2\nPLEASE READ OUT .1\nDO GIVE UP\n");
+    let mut optimizer = Optimizer::new(program, true);
+    optimizer.set_budget(Duration::from_secs(0));
+    let (program, stats) = optimizer.optimize();
+    // nothing was transformed, not even to constant output
+    assert!(stats.passes_skipped > 0);
+    assert_eq!(program.stmts.len(), 5);
+    // nothing ABSTAINs, and only .2 is STASHed
+    assert!(program.stmts.iter().all(|stmt| !stmt.can_abstain));
+    let stashable: Vec<_> = program.var_info.0.iter().map(|info| info.can_stash).collect();
+    assert_eq!(stashable, [false, true]);
+}
+
+#[test]
+fn folded_numbers_keep_16_bit_width() {
+    let program = parse("DO .1 <- #1 $ #2\nDO :1 <- #65535 $ #1\nDO :2 <- '&#0$#1'\n\
+                         PLEASE GIVE UP\n");
+    let program = Optimizer::opt_constant_fold(program);
+    let values: Vec<_> = program.stmts[..3].iter().map(|stmt| match stmt.body {
+        StmtBody::Calc(_, ref expr) => expr.clone(),
+        _ => panic!("not a calculation"),
+    }).collect();
+    // #0 $ #1 folds to a 16-bit #1, but the AND stays 32-bit as parsed
+    assert_eq!(values, [Expr::Num(VType::I16, 6), Expr::Num(VType::I32, 0xAAAA_AAAB),
+                        Expr::Num(VType::I32, 0)]);
+}
+
+#[test]
+fn lenient_mode_skips_bad_input() {
+    let program = parse("DO WRITE IN .1\nDO WRITE IN .1\nDO WRITE IN .1\nPLEASE WRITE IN .1\n\
+                         PLEASE READ OUT .1\nDO GIVE UP\n");
+    // an unknown word, then numbers too large for 32 and for 16 bits
+    let mut stdin = Cursor::new(&b"BLUE\nFOUR TWO NINE FOUR NINE SIX SEVEN TWO NINE SIX\n\
+                                   SEVEN SEVEN SEVEN SEVEN SEVEN\nFIVE\n"[..]);
+    let mut stdout = Vec::new();
+    let mut diagnostics = Vec::new();
+    {
+        let mut eval = Eval::new(&program, &mut stdin, &mut stdout, false, false);
+        eval.set_lenient(&mut diagnostics);
+        eval.eval().unwrap();
+    }
+    assert_eq!(stdout, b" \nV\n");
+    let diagnostics = String::from_utf8(diagnostics).unwrap();
+    let codes: Vec<_> = diagnostics.lines().filter(|line| line.starts_with("ICL"))
+                                   .map(|line| &line[..7]).collect();
+    assert_eq!(codes, ["ICL579I", "ICL533I", "ICL275I"]);
+
+    // a mingle that overflows is still fatal
+    let program = parse("DO :2 <- #256 $ #0\nDO :1 <- :2 $ #1\nPLEASE GIVE UP\n");
+    let mut stdin = Cursor::new(&b""[..]);
+    let mut stdout = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut eval = Eval::new(&program, &mut stdin, &mut stdout, false, false);
+    eval.set_lenient(&mut diagnostics);
+    let res = eval.eval();
+    assert!(res.unwrap_err().to_string().starts_with("ICL533I"));
+}
+
+#[test]
+fn specializing_needs_constant_output() {
+    // like -F with --specialize on the command line
+    let mut pipeline = Pipeline::new();
+    pipeline.set_optimize(true, false);
+    pipeline.set_fixed_input(b"TWO\n");
+    let err = pipeline.compile("DO WRITE IN .1\nPLEASE READ OUT .1\nDO GIVE UP\n").unwrap_err();
+    assert!(err.to_string().starts_with("ICL990I"), "{}", err.to_string());
+}
+
+#[test]
+fn summary_is_written_on_give_up() {
+    // the second statement is a splat, which is never run
+    let program = parse("DO (1) NEXT\nDO FROBNICATE\n(1) PLEASE READ OUT #2\nDO GIVE UP\n");
+    let mut stdin = Cursor::new(&b""[..]);
+    let mut stdout = Vec::new();
+    let mut summary = Vec::new();
+    {
+        let mut eval = Eval::new(&program, &mut stdin, &mut stdout, false, false);
+        eval.set_summary(&mut summary);
+        eval.eval().unwrap();
+    }
+    assert_eq!(String::from_utf8(summary).unwrap(),
+               "GIVEN UP after 3 statements (3 executed), NEXT stack depth 1, 1 splats, \
+                0 errors recovered, 6 bytes output\n");
+}
+
+#[test]
+fn eval_run_reads_input_and_reports_outcome() {
+    let program = parse("DO WRITE IN .1\nDO .2 <- #3\nPLEASE (1009) NEXT\nDO READ OUT .3\n\
+                         DO GIVE UP\n");
+    let (output, outcome) = Eval::run(&program, b"FOUR\n", Some(1)).unwrap();
+    assert_eq!(output, b"   \nVII\n");
+    assert_eq!(outcome.termination, Termination::GaveUp);
+    // the program's own five statements, and some of the syslib's
+    assert!(outcome.stmts > 5);
+}
+
+#[test]
+fn select_fast_path_agrees_with_bit_loop() {
+    // select by definition: the bits of v under the mask, packed to the right
+    fn select_bits(v: u32, w: u32) -> u32 {
+        let (mut res, mut pos) = (0, 0);
+        for bit in 0..32 {
+            if w & (1 << bit) != 0 {
+                res |= ((v >> bit) & 1) << pos;
+                pos += 1;
+            }
+        }
+        res
+    }
+    let mut state = 0x2545_F491u32;
+    let mut random = || {
+        // xorshift, to get the same masks every time
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    for _ in 0..1000 {
+        let v = random();
+        // a contiguous run of ones, then an arbitrary mask
+        let (start, len) = (random() % 32, random() % 32 + 1);
+        let run = (u64::MAX >> (64 - len) << start) as u32;
+        for &w in &[run, random(), 0, u32::MAX] {
+            assert_eq!(select(v, w), select_bits(v, w), "{:#x} ~ {:#x}", v, w);
+        }
+    }
+}
+
+#[test]
+fn constant_output_program_has_one_type_per_statement() {
+    let program = Optimizer::opt_const_output(parse("PLEASE READ OUT #1\nDO GIVE UP\n"));
+    assert_eq!(program.stmt_types.len(), program.stmts.len());
+    let program = Optimizer::opt_abstain_check(program);
+    // the Print cannot be abstained from, GIVE UP is left alone
+    let can_abstain: Vec<_> = program.stmts.iter().map(|stmt| stmt.can_abstain).collect();
+    assert_eq!(can_abstain, [false, true]);
+    assert_eq!(output(&program), " \nI\n");
+}
+
+#[test]
+fn empty_and_zero_dimensions_are_refused() {
+    for dims in &[vec![], vec![0], vec![2, 0]] {
+        let mut array = Bind::new(Array::<u16>::new(vec![1]));
+        let err = array.dimension(dims.clone(), 0).unwrap_err();
+        assert!(err.to_string().starts_with("ICL240I"), "{:?}: {}", dims, err.to_string());
+    }
+    let program = parse("DO ,1 <- #0\nPLEASE GIVE UP\n");
+    let (res, _) = with_eval(&program, &[], |eval| eval.eval());
+    assert!(res.unwrap_err().to_string().starts_with("ICL240I"));
+}
+
+#[test]
+fn unseparated_numbers_run_together() {
+    let program = parse("DO READ OUT #1 + #4\nDO READ OUT #2\nPLEASE GIVE UP\n");
+    let (res, output) = with_eval(&program, &[], |eval| {
+        eval.set_roman_sep(RomanSep::None);
+        eval.eval()
+    });
+    res.unwrap();
+    // both READ OUTs share one row, without any space in between
+    assert_eq!(output, "     \nIIVII\n");
+}
+
+#[test]
+fn retrieving_unstashed_vars_is_flagged() {
+    let program = parse("DO STASH .1 + ,3\nDO .2 <- #1\nDO RETRIEVE .1 + .2\n\
+                         DO RETRIEVE :1 + ,3\nPLEASE GIVE UP\n");
+    let suspicious: Vec<_> = Optimizer::check_stash_balance(&program).into_iter()
+        .map(|(var, srcline)| (program.source_var(&var).to_string(), srcline)).collect();
+    assert_eq!(suspicious, [(".2".to_string(), 3), (":1".to_string(), 4)]);
+}
+
+#[test]
+fn error_marker_points_at_next_statement() {
+    let code = "DO .1 <- #1\n\tDO .1 <- #2 PLEASE RESUME #0 DO .2 <- #3\nDO GIVE UP\n";
+    let err = Eval::run(&parse(code), &[], Some(0)).unwrap_err();
+    // the tab is kept, so that the marker lines up below the DO
+    let marker = " ".repeat(29);
+    assert!(err.display_with_source(code).ends_with(
+        &format!("\tDO .1 <- #2 PLEASE RESUME #0 DO .2 <- #3\n\t{}^\n", marker)),
+        "{}", err.display_with_source(code));
+}
+
+#[test]
+fn rewrites_that_are_not_cheaper_are_dropped() {
+    fn mingle_to_select(expr: &Expr) -> Option<Expr> {
+        match *expr {
+            Expr::Mingle(ref vx, ref wx) =>
+                Some(Expr::Select(VType::I32, vx.clone(), wx.clone())),
+            _ => None,
+        }
+    }
+    fn mingle_to_or(expr: &Expr) -> Option<Expr> {
+        match *expr {
+            Expr::Mingle(ref vx, ref wx) => Some(Expr::RsOr(vx.clone(), wx.clone())),
+            _ => None,
+        }
+    }
+    let program = parse("DO :1 <- .1 $ .2\nPLEASE GIVE UP\n");
+    // a select costs just as much as a mingle, so it is not put in
+    let selected = Optimizer::opt_expressions_with(program.clone(), &[mingle_to_select]);
+    assert_eq!(selected.stmts[0].body, program.stmts[0].body);
+    let ored = Optimizer::opt_expressions_with(program, &[mingle_to_or]);
+    let or = Expr::RsOr(Box::new(Expr::Var(Var::I16(0))), Box::new(Expr::Var(Var::I16(1))));
+    assert_eq!(ored.stmts[0].body, StmtBody::Calc(Var::I32(0), or));
+}
+
+#[test]
+fn skipped_constant_output_is_reported() {
+    let constant = parse("DO .1 <- #3\nPLEASE READ OUT .1\nDO GIVE UP\n");
+    let (program, stats) = Optimizer::new(constant.clone(), false).optimize();
+    assert!(stats.const_out_skipped);
+    assert_eq!(program.stmts.len(), constant.stmts.len());
+    // a program reading input could not have been optimized anyway
+    let reading = parse("DO WRITE IN .1\nPLEASE READ OUT .1\nDO GIVE UP\n");
+    let (_, stats) = Optimizer::new(reading, false).optimize();
+    assert!(!stats.const_out_skipped);
+}
+
+#[test]
+fn skipped_statements_are_not_counted_as_executed() {
+    let program = parse("DO %0 .1 <- #1\nDO NOT .2 <- #2\nDO .3 <- #3\nPLEASE GIVE UP\n");
+    let ((visited, executed), _) = with_eval(&program, &[], |eval| {
+        let outcome = eval.eval().unwrap();
+        (outcome.stmts, eval.executed_count())
+    });
+    // the 0% statement and the abstained one are visited, but not executed
+    assert_eq!(visited, 4);
+    assert_eq!(executed, 2);
+}
+
+#[test]
+fn batched_numbers_share_a_row_per_read_out() {
+    let program = parse("DO READ OUT #1 + #4\nDO READ OUT #2\nPLEASE GIVE UP\n");
+    let (res, output) = with_eval(&program, &[], |eval| {
+        eval.set_roman_sep(RomanSep::Batch);
+        eval.eval()
+    });
+    res.unwrap();
+    assert_eq!(output, "    \nI IV\n  \nII\n");
+}
+
+#[test]
+fn next_overflow_names_the_next_stack() {
+    let program = parse("DO .1 <- #1\n(1) DO (1) NEXT\nPLEASE GIVE UP\n");
+    let err = Eval::run(&program, &[], Some(0)).unwrap_err();
+    assert!(err.to_string().starts_with("ICL123I"), "{}", err.to_string());
+    // all 80 entries come from the labeled NEXT on line 2
+    let chain = vec!["2 (1)"; 80].join(", ");
+    assert_eq!(err.short_string(), format!("NEXTED FROM LINES {}", chain));
+}
+
+#[test]
+fn tape_state_follows_successive_byte_io() {
+    let program = parse("DO ,1 <- #2\nDO WRITE IN ,1\nDO READ OUT ,1\nPLEASE WRITE IN ,1\n\
+                         DO READ OUT ,1\nPLEASE GIVE UP\n");
+    let mut stdin = Cursor::new(&b"ACDE"[..]);
+    let mut stdout = Vec::new();
+    let mut eval = Eval::new(&program, &mut stdin, &mut stdout, false, false);
+    eval.eval().unwrap();
+    let (last_in, last_out) = eval.tape_state();
+    // the second WRITE IN goes on from where the first one left the tape
+    assert_eq!(last_in, b'E');
+    assert_eq!(stdout.len(), 4);
+    assert_eq!(last_out, stdout[3].reverse_bits());
+}
+
+#[test]
+fn input_and_label_errors_exit_differently() {
+    let eof = Eval::run(&parse("DO WRITE IN .1\nPLEASE GIVE UP\n"), &[], Some(0)).unwrap_err();
+    assert!(eof.to_string().starts_with("ICL562I"), "{}", eof.to_string());
+    let missing = Eval::run(&parse("DO (5) NEXT\nPLEASE GIVE UP\n"), &[], Some(0)).unwrap_err();
+    assert!(missing.to_string().starts_with("ICL129I"), "{}", missing.to_string());
+    // running out of input is bad data, a missing label is just an error
+    assert_eq!(eof.exit_code(), 65);
+    assert_eq!(missing.exit_code(), 1);
+}
+
+#[test]
+fn arrays_over_the_element_cap_are_refused() {
+    let program = parse("DO ,1 <- #2 BY #3\nDO ,1 SUB #2 #3 <- #5\nPLEASE READ OUT ,1 SUB #2 #3\n\
+                         DO ;1 <- #7\nDO GIVE UP\n");
+    let (res, output) = with_eval(&program, &[], |eval| {
+        eval.set_max_elems(6);
+        eval.eval()
+    });
+    // six elements are just fine, seven are too many
+    assert_eq!(output, " \nV\n");
+    assert!(res.unwrap_err().to_string().starts_with("ICL241I"));
+}
+
+#[test]
+fn recursion_depth_is_recorded() {
+    // (1) NEXTs to itself until (9) has been reinstated three times, then (9)
+    // jumps once more and RESUMEs everything
+    let program = parse("PLEASE ABSTAIN #3 FROM (9)\nDO (1) NEXT\nPLEASE GIVE UP\n\
+                         (1) DO REINSTATE (9)\n(9) DO (2) NEXT\nDO (1) NEXT\n(2) DO RESUME #4\n");
+    let (depth, _) = with_eval(&program, &[], |eval| {
+        eval.eval().unwrap();
+        eval.max_jump_depth()
+    });
+    assert_eq!(depth, 4);
+}
+
+#[test]
+fn digit_input_mixes_words_and_digits() {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_input_mode(InputMode::Digits);
+    let program = pipeline.compile("DO WRITE IN :1\nPLEASE READ OUT :1\nDO GIVE UP\n").unwrap();
+    for &(input, value) in &[(&b"ONE 2 THREE\n"[..], 123u32), (b"123\n", 123),
+                             (b"FOUR 294967295\n", u32::MAX)] {
+        pipeline.set_input(input);
+        assert_eq!(pipeline.run(&program).unwrap().output, to_roman(value).into_bytes());
+    }
+    // one more than fits in 32 bits, however it is written
+    for &input in &[&b"4294967296\n"[..], b"FOUR 2 NINE 4967296\n"] {
+        pipeline.set_input(input);
+        let err = pipeline.run(&program).unwrap_err();
+        assert!(err.to_string().starts_with("ICL533I"), "{}", err.to_string());
+    }
+}
+
+#[test]
+fn endless_output_stops_at_the_byte_cap() {
+    let program = parse("DO COME FROM (2)\nDO READ OUT #1\n(2) PLEASE .1 <- #1\n");
+    let (res, output) = with_eval(&program, &[], |eval| {
+        eval.set_max_output(10);
+        eval.eval()
+    });
+    // two numbers fit, the third one would go over the cap
+    assert_eq!(output, " \nI\n \nI\n");
+    let err = res.unwrap_err();
+    assert!(err.to_string().starts_with("ICL252I"), "{}", err.to_string());
+}
+
+#[test]
+fn numbers_are_padded_to_the_roman_width() {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_roman_width(8);
+    let program = pipeline.compile("DO READ OUT #1 + #1888\nPLEASE GIVE UP\n").unwrap();
+    // numbers wider than that are not cut off
+    assert_eq!(String::from_utf8(pipeline.run(&program).unwrap().output).unwrap(),
+               format!("{:8}\n{:8}\n{:13}\nMDCCCLXXXVIII\n", "", "I", ""));
+}
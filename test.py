@@ -33,7 +33,7 @@ def run_test(testname, testcode, compiled):
     with open(testname + '.chk', 'r') as stdoutfile:
         stdout = stdoutfile.read()
 
-    def check(proc, remove_cargo):
+    def output(proc, remove_cargo):
         real_stdout, _ = proc.communicate(stdin)
         real_stdout = real_stdout.decode()
         # remove cargo's "Running" line
@@ -43,6 +43,10 @@ def run_test(testname, testcode, compiled):
                 errindex = real_stdout.find('error: Process didn\'t exit successfully')
             if errindex > -1:
                 real_stdout = real_stdout[:errindex]
+        return real_stdout
+
+    def check(proc, remove_cargo):
+        real_stdout = output(proc, remove_cargo)
         if real_stdout != stdout:
             print('*** ERROR: standard output does not match check file')
             print(''.join(difflib.unified_diff(stdout.splitlines(True),
@@ -62,7 +66,13 @@ def run_test(testname, testcode, compiled):
     if compiled:
         print('  > Step 3: compiled + optimized')
         if testcode not in already_compiled:
-            if os.system('cargo run --release -q -- -RFbo %s > /dev/null' % testcode) != 0:
+            proc = Popen(['cargo', 'run', '--release', '-q', '--', '-RFbo', testcode],
+                         stdin=PIPE, stdout=PIPE, stderr=STDOUT)
+            compiler_stdout = output(proc, True)
+            if proc.returncode != 0:
+                # for programs the compiler must reject, its error is the expected output
+                if compiler_stdout == stdout:
+                    return
                 print('*** ERROR: compilation failed')
                 raise RuntimeError
             already_compiled.add(testcode)